@@ -0,0 +1,41 @@
+use crate::config::Settings;
+
+const USAGE: &str = "Usage:
+  nc-teltonika-server                 start the server
+  nc-teltonika-server config check    print the effective config (secrets redacted) and validate it";
+
+/// Handle a subcommand and return its exit code, or `None` to start the server.
+pub fn run(args: &[String]) -> Option<i32> {
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => None,
+        ["config", "check"] => Some(config_check()),
+        _ => {
+            eprintln!("{}", USAGE);
+            Some(2)
+        }
+    }
+}
+
+fn config_check() -> i32 {
+    let settings = match Settings::new() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("❌ Failed to load configuration: {}", e);
+            return 1;
+        }
+    };
+
+    println!("# Effective configuration (APP_ENV={})", settings.env);
+    println!("{}", serde_json::to_string_pretty(&settings.redacted()).unwrap_or_default());
+
+    let problems = settings.validate();
+    if problems.is_empty() {
+        println!("✅ Configuration is valid");
+        0
+    } else {
+        for problem in &problems {
+            eprintln!("❌ {}", problem);
+        }
+        1
+    }
+}
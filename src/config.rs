@@ -1,85 +1,668 @@
-use config::{Config, ConfigError, Environment};
-use serde::Deserialize;
+use config::{Config, ConfigError, Environment, File, FileFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use arc_swap::ArcSwap;
+use tokio::sync::watch;
+use tracing::{info, warn};
 
-#[derive(Debug, Deserialize, Clone)]
+use crate::geofence::GeofenceSettings;
+use crate::notifications::ChannelSettings;
+use crate::parser::io_elements::IoElementOverride;
+use crate::session::DuplicatePolicy;
+use crate::webhook::subscribers::{SubscriberSettings, NAUTICONCEPT_SUBSCRIBER};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ServerSettings {
     pub port: u16,
-    pub http_port: u16,
     pub monitor_port: u16,
+    /// Idle time before a device connection is closed.
+    pub inactivity_timeout_ms: u64,
+    /// Per device group overrides of `inactivity_timeout_ms` (group name -> ms),
+    /// for trackers with long sleep modes.
+    #[serde(default)]
+    pub group_timeouts_ms: HashMap<String, u64>,
+    /// Concurrent device connections; further clients wait in the accept backlog.
+    pub max_connections: usize,
+    /// On SIGTERM/SIGINT, time allowed to finish in-flight packets and webhook deliveries.
+    pub shutdown_timeout_ms: u64,
+    /// Bearer token required by `POST /admin/reload`; the endpoint is disabled when unset.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    #[serde(default)]
+    pub tls: TlsSettings,
+}
+
+/// Optional TLS listener for devices, next to the plaintext `server.port`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TlsSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub port: u16,
+    /// PEM certificate chain and private key; re-read when the files change.
+    #[serde(default)]
+    pub cert_path: String,
+    #[serde(default)]
+    pub key_path: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DatabaseSettings {
     pub host: String,
     pub port: u16,
     pub user: String,
     pub password: String,
     pub name: String,
+    pub pool_size: u32,
+    /// Longest wait for a pooled connection before a query fails.
+    pub acquire_timeout_ms: u64,
+    /// Cap of the backoff between connection attempts while the database is unreachable at startup.
+    pub connect_backoff_max_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StorageSettings {
+    /// "postgres", "sqlite" or "memory"
+    pub backend: String,
+    pub sqlite_path: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct SshSettings {
     pub user: Option<String>,
     pub host: Option<String>,
+    /// SSH server port (default 22).
+    pub port: Option<u16>,
+    /// Private key; without one the running ssh-agent is used.
     pub key_path: Option<String>,
+    pub key_passphrase: Option<String>,
+    /// OpenSSH known_hosts file the server key must be listed in (default `~/.ssh/known_hosts`).
+    pub known_hosts_path: Option<String>,
+    /// Postgres port on the SSH host (default 5432), forwarded to 127.0.0.1:`database.port`.
     pub tunnel_port: Option<u16>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl SshSettings {
+    /// The tunnel is used when `ssh.user` is set.
+    pub fn enabled(&self) -> bool {
+        self.user.as_deref().is_some_and(|u| !u.is_empty())
+    }
+
+    pub fn known_hosts_file(&self) -> PathBuf {
+        match self.known_hosts_path.as_deref().filter(|p| !p.is_empty()) {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(env::var("HOME").unwrap_or_default()).join(".ssh/known_hosts"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WebhookSettings {
     pub nauticoncept_url: String,
+    /// HMAC secret for the Nauticoncept endpoint (see webhook::signature).
+    pub nauticoncept_secret: Option<String>,
     pub teams_url: String,
+    /// Send the decoded records in the body; when false only a compact summary is sent.
+    pub include_records: bool,
+    /// Opt-in for consumers that still expect the old empty `{}` body.
+    pub legacy_empty_body: bool,
+    /// Per-request timeout for webhook deliveries.
+    pub timeout_ms: u64,
+    /// Attempts before a job is moved to `webhook_dead_letter`.
+    pub max_attempts: i32,
+    pub backoff_base_ms: u64,
+    pub backoff_max_ms: u64,
+    /// How often the worker re-checks the queue for retries that became due.
+    pub poll_interval_ms: u64,
+    pub batch_size: i64,
+    /// Extra endpoints, each with its own IMEI/event filters and headers.
+    #[serde(default)]
+    pub subscribers: Vec<SubscriberSettings>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotificationSettings {
+    /// Alert channels, each receiving messages at or above its `min_severity`.
+    #[serde(default)]
+    pub channels: Vec<ChannelSettings>,
+    /// Identical alerts within this window are collapsed into one digest.
+    pub coalesce_window_secs: u64,
+    /// Alerts waiting for the dispatcher; extra ones are dropped.
+    pub queue_size: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetricsSettings {
+    /// Export `device_last_seen_timestamp_seconds{imei}`.
+    pub device_last_seen: bool,
+    /// Cap on IMEIs with their own series.
+    pub max_devices: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LimitSettings {
+    /// Concurrent connections from one source IP (0 = unlimited).
+    pub max_connections_per_ip: usize,
+    /// AVL packets per IMEI and minute; further packets close the connection without ACK (0 = unlimited).
+    pub max_packets_per_minute: u32,
+    /// Failed handshakes within `handshake_failure_window_secs` before an IP is banned (0 = never).
+    pub handshake_failures_before_ban: u32,
+    pub handshake_failure_window_secs: u64,
+    pub ban_secs: u64,
+    /// Addresses never capped or banned, e.g. a carrier NAT gateway.
+    #[serde(default)]
+    pub exempt_ips: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DevicesSettings {
+    /// Only registered IMEIs may connect; others get a 0x00 handshake reply.
+    pub enforce: bool,
+    /// Extra allowed IMEIs, one per line (`#` comments). Re-read on reload.
+    pub allowlist_file: String,
+    /// Record rejected unknown IMEIs in `teltonika_devices` as 'quarantine'.
+    pub auto_enroll: bool,
+    /// Accept devices when the registry cannot be read (database down).
+    pub fail_open: bool,
+    /// Accept 16-digit IMEISVs in the handshake, not only 15-digit IMEIs.
+    pub allow_imeisv: bool,
+    /// A second connection for an IMEI that still has one: close_old, reject_new or allow.
+    pub duplicate_sessions: DuplicatePolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PresenceSettings {
+    /// Run the offline/online watcher.
+    pub enabled: bool,
+    /// How often devices are expected to report.
+    pub expected_interval_secs: u64,
+    /// Intervals without a packet before a device is offline.
+    pub missed_intervals: u32,
+    pub check_interval_secs: u64,
+    /// Per-group `expected_interval_secs` (group name from `[groups]`).
+    #[serde(default)]
+    pub group_intervals_secs: HashMap<String, u64>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LogSettings {
+    /// `EnvFilter` directives (e.g. "info,sqlx=warn"); empty falls back to `RUST_LOG`.
+    #[serde(default)]
+    pub filter: String,
+    /// "text" or "json" (structured lines for ELK/Loki).
+    #[serde(default)]
+    pub format: String,
+    #[serde(default)]
+    pub otlp: OtlpSettings,
+}
+
+/// OpenTelemetry span export over OTLP/HTTP (protobuf). Startup only.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OtlpSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Full traces URL (e.g. "http://collector:4318/v1/traces"); empty uses
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` or http://localhost:4318/v1/traces.
+    #[serde(default)]
+    pub endpoint: String,
+    #[serde(default)]
+    pub service_name: String,
+    /// Share of connections traced, 0.0 - 1.0.
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+impl Default for OtlpSettings {
+    fn default() -> Self {
+        OtlpSettings { enabled: false, endpoint: String::new(), service_name: String::new(), sample_ratio: default_sample_ratio() }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Settings {
     pub server: ServerSettings,
     pub database: DatabaseSettings,
+    pub storage: StorageSettings,
+    #[serde(default)]
     pub ssh: SshSettings,
     pub webhook: WebhookSettings,
+    pub notifications: NotificationSettings,
+    pub metrics: MetricsSettings,
+    pub devices: DevicesSettings,
+    pub limits: LimitSettings,
+    pub presence: PresenceSettings,
+    #[serde(default)]
+    pub log: LogSettings,
+    /// Label/unit overrides and additions for the built-in IO element catalog (IO id -> entry).
+    #[serde(default)]
+    pub io_catalog: HashMap<String, IoElementOverride>,
+    /// Named device groups (group name -> IMEIs).
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+    /// Zones checked against every GPS fix (`[[geofences]]`).
+    #[serde(default)]
+    pub geofences: Vec<GeofenceSettings>,
     pub env: String,
 }
 
+/// Built-in defaults, so the binary runs even without a `config/` directory.
+const DEFAULT_CONFIG: &str = include_str!("../config/default.toml");
+
+/// Pre-`APP_*` env var names still used by existing `.env` files.
+const LEGACY_ENV_VARS: &[(&str, &str)] = &[
+    ("server.port", "HTTP_SERVER_PORT"),
+    ("server.monitor_port", "MONITOR_PORT"),
+    ("database.host", "DB_HOST"),
+    ("database.port", "DB_PORT"),
+    ("database.user", "DB_USER"),
+    ("database.password", "DB_PASSWORD"),
+    ("database.name", "DB_NAME"),
+    ("storage.backend", "STORAGE_BACKEND"),
+    ("storage.sqlite_path", "SQLITE_PATH"),
+    ("ssh.user", "SSH_USER"),
+    ("ssh.host", "SSH_HOST"),
+    ("ssh.key_path", "SSH_PRIVATE_KEY_PATH"),
+    ("ssh.known_hosts_path", "SSH_KNOWN_HOSTS_PATH"),
+    ("webhook.nauticoncept_url", "NAUTICONCEPT_API_URL"),
+    ("webhook.nauticoncept_secret", "NAUTICONCEPT_WEBHOOK_SECRET"),
+    ("webhook.include_records", "WEBHOOK_INCLUDE_RECORDS"),
+    ("webhook.legacy_empty_body", "WEBHOOK_LEGACY_EMPTY_BODY"),
+    ("webhook.teams_url", "TEAMS_WEBHOOK_URL"),
+    ("log.format", "RUST_LOG_FORMAT"),
+];
+
+fn legacy_env_source() -> Result<Config, ConfigError> {
+    let mut builder = Config::builder();
+    for (key, var) in LEGACY_ENV_VARS {
+        builder = builder.set_override_option(*key, env::var(var).ok())?;
+    }
+    builder.build()
+}
+
+/// `APP__SERVER__PORT` (the original form, still in older deployments) and
+/// `APP_SERVER__PORT`; the latter wins when both are set. `vars` replaces the
+/// process environment in tests.
+fn app_env_sources(vars: Option<config::Map<String, String>>) -> [Environment; 2] {
+    [
+        Environment::default().prefix("APP").prefix_separator("__").separator("__").source(vars.clone()),
+        Environment::default().prefix("APP").prefix_separator("_").separator("__").source(vars),
+    ]
+}
+
 impl Settings {
+    /// Layers, lowest priority first:
+    /// 1. embedded `config/default.toml`
+    /// 2. `{APP_CONFIG_DIR}/default.toml` (optional)
+    /// 3. `{APP_CONFIG_DIR}/{APP_ENV}.toml` (optional)
+    /// 4. legacy flat env vars (`DB_HOST`, `HTTP_SERVER_PORT`, ...)
+    /// 5. `APP_*` env vars (`APP_SERVER__PORT=6000` or `APP__SERVER__PORT=6000` maps to `server.port`)
     pub fn new() -> Result<Self, ConfigError> {
         let env_run = env::var("APP_ENV").unwrap_or_else(|_| "development".into());
+        let config_dir = env::var("APP_CONFIG_DIR").unwrap_or_else(|_| "config".into());
 
+        let [legacy_prefix, prefix] = app_env_sources(None);
         let builder = Config::builder()
-            // Start with default values (could be in a default.toml, but here we set via set_default if needed)
-            // .add_source(File::with_name("config/default")) 
-            // .add_source(File::with_name(&format!("config/{}", env_run)).required(false))
-            // Load from Environment Variables
-            // APP_SERVER__PORT=6000 maps to settings.server.port
-            .add_source(Environment::default().separator("__").prefix("APP"))
-            // Compatibility with existing flat Env Vars (Manual mapping)
-            .set_default("server.port", env::var("HTTP_SERVER_PORT").unwrap_or("6000".into()))?
-            .set_default("server.http_port", env::var("HTTP_SERVER_PORT").unwrap_or("6000".into()))? // Assuming same unless specified
-            .set_default("server.monitor_port", env::var("MONITOR_PORT").unwrap_or("9090".into()))?
-            
-            .set_default("database.host", env::var("DB_HOST").unwrap_or("127.0.0.1".into()))?
-            .set_default("database.port", env::var("DB_PORT").unwrap_or("5432".into()))?
-            .set_default("database.user", env::var("DB_USER").unwrap_or("".into()))?
-            .set_default("database.password", env::var("DB_PASSWORD").unwrap_or("".into()))?
-            .set_default("database.name", env::var("DB_NAME").unwrap_or("".into()))?
-            
-            .set_default("ssh.user", env::var("SSH_USER").ok())?
-            .set_default("ssh.host", env::var("SSH_HOST").ok())?
-            .set_default("ssh.key_path", env::var("SSH_PRIVATE_KEY_PATH").ok())?
-            // .set_default("ssh.tunnel_port", ...)?
-            
-             .set_default("webhook.nauticoncept_url", env::var("NAUTICONCEPT_API_URL").unwrap_or("".into()))?
-             // Hardcoded fallback for now, but should be env var
-             .set_default("webhook.teams_url", "https://nauticoncept.webhook.office.com/webhookb2/3e85c63b-47b3-40b5-aed1-38cd7782b8e3@0b54a401-b5bc-4c03-b505-f690c8c5de4b/IncomingWebhook/41a874a7910a4f09b71baadd3afdb46a/752cea04-da22-4bd2-bed1-a81f4b884fef/V2CTD5qoUoRXUQyO6fvocB37SikQRGLVmkdpXP2YO1SZs1")?
-             
+            .add_source(File::from_str(DEFAULT_CONFIG, FileFormat::Toml))
+            .add_source(File::with_name(&format!("{}/default", config_dir)).required(false))
+            .add_source(File::with_name(&format!("{}/{}", config_dir, env_run)).required(false))
+            .add_source(legacy_env_source()?)
+            .add_source(legacy_prefix)
+            .add_source(prefix)
             .set_default("env", env_run)?;
 
         builder.build()?.try_deserialize()
     }
+
+    /// Problems that would only surface later at runtime. Empty when the config is usable.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.server.port == self.server.monitor_port {
+            problems.push(format!("server.port and server.monitor_port are both {}", self.server.port));
+        }
+        if self.server.tls.enabled {
+            if self.server.tls.port == self.server.port || self.server.tls.port == self.server.monitor_port {
+                problems.push(format!("server.tls.port {} is already used by another listener", self.server.tls.port));
+            }
+            if let Err(e) = crate::tls::load_server_config(&self.server.tls) {
+                problems.push(format!("server.tls: {}", e));
+            }
+        }
+
+        match self.storage.backend.to_lowercase().as_str() {
+            "postgres" | "postgresql" => {
+                if self.database.host.is_empty() {
+                    problems.push("database.host is empty".to_string());
+                }
+                if self.database.name.is_empty() {
+                    problems.push("database.name is empty".to_string());
+                }
+            }
+            "sqlite" => {
+                if self.storage.sqlite_path.is_empty() {
+                    problems.push("storage.sqlite_path is empty".to_string());
+                }
+            }
+            "memory" => {}
+            other => problems.push(format!("storage.backend '{}' is not one of postgres, sqlite, memory", other)),
+        }
+
+        if let Some(user) = self.ssh.user.as_deref().filter(|u| !u.is_empty()) {
+            if self.ssh.host.as_deref().unwrap_or("").is_empty() {
+                problems.push(format!("ssh.user is '{}' but ssh.host is empty", user));
+            }
+            if let Some(key) = self.ssh.key_path.as_deref().filter(|k| !k.is_empty()) {
+                if !Path::new(key).is_file() {
+                    problems.push(format!("ssh.key_path '{}' does not exist", key));
+                }
+            }
+            let known_hosts = self.ssh.known_hosts_file();
+            if !known_hosts.is_file() {
+                problems.push(format!("ssh known_hosts file '{}' does not exist", known_hosts.display()));
+            }
+        }
+
+        for ip in &self.limits.exempt_ips {
+            if ip.parse::<std::net::IpAddr>().is_err() {
+                problems.push(format!("limits.exempt_ips: '{}' is not an IP address", ip));
+            }
+        }
+
+        if !self.devices.allowlist_file.is_empty() && !Path::new(&self.devices.allowlist_file).is_file() {
+            problems.push(format!("devices.allowlist_file '{}' does not exist", self.devices.allowlist_file));
+        }
+
+        if self.presence.expected_interval_secs == 0 || self.presence.missed_intervals == 0 || self.presence.check_interval_secs == 0 {
+            problems.push("presence.expected_interval_secs, missed_intervals and check_interval_secs must be greater than 0".to_string());
+        }
+        for (group, secs) in &self.presence.group_intervals_secs {
+            if !self.groups.contains_key(group) {
+                problems.push(format!("presence.group_intervals_secs uses unknown group '{}'", group));
+            }
+            if *secs == 0 {
+                problems.push(format!("presence.group_intervals_secs.{} must be greater than 0", group));
+            }
+        }
+
+        if self.server.inactivity_timeout_ms == 0 {
+            problems.push("server.inactivity_timeout_ms must be greater than 0".to_string());
+        }
+        if self.server.shutdown_timeout_ms == 0 {
+            problems.push("server.shutdown_timeout_ms must be greater than 0".to_string());
+        }
+        if self.server.max_connections == 0 {
+            problems.push("server.max_connections must be greater than 0".to_string());
+        }
+        for group in self.server.group_timeouts_ms.keys() {
+            if !self.groups.contains_key(group) {
+                problems.push(format!("server.group_timeouts_ms uses unknown group '{}'", group));
+            }
+        }
+        if self.database.acquire_timeout_ms == 0 {
+            problems.push("database.acquire_timeout_ms must be greater than 0".to_string());
+        }
+        if self.database.pool_size == 0 {
+            problems.push("database.pool_size must be greater than 0".to_string());
+        }
+
+        if self.webhook.max_attempts < 1 {
+            problems.push("webhook.max_attempts must be at least 1".to_string());
+        }
+        if self.webhook.timeout_ms == 0 {
+            problems.push("webhook.timeout_ms must be greater than 0".to_string());
+        }
+
+        let mut names = HashSet::new();
+        for subscriber in &self.webhook.subscribers {
+            if !names.insert(subscriber.name.as_str()) {
+                problems.push(format!("webhook subscriber '{}' is declared twice", subscriber.name));
+            }
+            if subscriber.name == NAUTICONCEPT_SUBSCRIBER {
+                problems.push(format!("webhook subscriber name '{}' is reserved for webhook.nauticoncept_url", subscriber.name));
+            }
+            if subscriber.url.is_empty() {
+                problems.push(format!("webhook subscriber '{}' has no url", subscriber.name));
+            }
+            if let Some(group) = &subscriber.group {
+                if !self.groups.contains_key(group) {
+                    problems.push(format!("webhook subscriber '{}' uses unknown group '{}'", subscriber.name, group));
+                }
+            }
+        }
+
+        let mut zones = HashSet::new();
+        for zone in &self.geofences {
+            if !zones.insert(zone.name.as_str()) {
+                problems.push(format!("geofence '{}' is declared twice", zone.name));
+            }
+            problems.extend(zone.problems(&self.groups));
+        }
+
+        for channel in &self.notifications.channels {
+            if let Err(e) = channel.build() {
+                problems.push(format!("notification channel '{}': {}", channel.name, e));
+            }
+        }
+
+        if !matches!(self.log.format.as_str(), "" | "text" | "json") {
+            problems.push(format!("log.format '{}' is not one of text, json", self.log.format));
+        }
+        if !(0.0..=1.0).contains(&self.log.otlp.sample_ratio) {
+            problems.push(format!("log.otlp.sample_ratio {} is not between 0 and 1", self.log.otlp.sample_ratio));
+        }
+
+        if !self.log.filter.is_empty() {
+            if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.filter) {
+                problems.push(format!("log.filter '{}': {}", self.log.filter, e));
+            }
+        }
+
+        for id in self.io_catalog.keys() {
+            if id.parse::<u16>().is_err() {
+                problems.push(format!("io_catalog key '{}' is not an IO id", id));
+            }
+        }
+
+        problems
+    }
+
+    /// Inactivity timeout for a device: the longest override among its groups, else the default.
+    pub fn inactivity_timeout_ms_for(&self, imei: &str) -> u64 {
+        self.server.group_timeouts_ms.iter()
+            .filter(|(group, _)| self.groups.get(*group).is_some_and(|members| members.iter().any(|i| i == imei)))
+            .map(|(_, ms)| *ms)
+            .max()
+            .unwrap_or(self.server.inactivity_timeout_ms)
+    }
+
+    /// Expected reporting interval of a device: the longest of its groups' overrides.
+    pub fn expected_interval_secs_for(&self, imei: &str) -> u64 {
+        self.presence.group_intervals_secs.iter()
+            .filter(|(group, _)| self.groups.get(*group).is_some_and(|members| members.iter().any(|i| i == imei)))
+            .map(|(_, secs)| *secs)
+            .max()
+            .unwrap_or(self.presence.expected_interval_secs)
+    }
+
+    /// Effective config as JSON with passwords, secrets, headers and the URLs of
+    /// alert channels and webhook subscribers masked.
+    pub fn redacted(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        redact(&mut value, false);
+        value
+    }
+}
+
+const REDACTED: &str = "***";
+
+/// Keys whose value is a credential, plus `url` inside alert channels and webhook subscribers (tokens in the URL).
+fn is_secret_key(key: &str, mask_urls: bool) -> bool {
+    matches!(key, "password" | "secret" | "admin_token" | "key_passphrase" | "nauticoncept_secret" | "teams_url" | "headers") || (mask_urls && key == "url")
+}
+
+fn redact(value: &mut serde_json::Value, mask_urls: bool) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if is_secret_key(key, mask_urls) {
+                    if !v.is_null() && v != "" {
+                        *v = serde_json::Value::String(REDACTED.to_string());
+                    }
+                } else {
+                    redact(v, mask_urls || key == "channels" || key == "subscribers");
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|v| redact(v, mask_urls)),
+        _ => {}
+    }
+}
+
+struct ConfigStore {
+    current: ArcSwap<Settings>,
+    changes: watch::Sender<Arc<Settings>>,
 }
 
-pub static CONFIG: std::sync::OnceLock<Settings> = std::sync::OnceLock::new();
+static CONFIG: OnceLock<ConfigStore> = OnceLock::new();
 
-pub fn get_settings() -> &'static Settings {
-    CONFIG.get_or_init(|| Settings::new().expect("Failed to load configuration"))
+fn store() -> &'static ConfigStore {
+    CONFIG.get_or_init(|| {
+        let settings = Arc::new(Settings::new().expect("Failed to load configuration"));
+        let (changes, _) = watch::channel(settings.clone());
+        ConfigStore { current: ArcSwap::new(settings), changes }
+    })
+}
+
+/// Current settings. Hold the `Arc` for the duration of one operation, not longer,
+/// so reloads are picked up.
+pub fn get_settings() -> Arc<Settings> {
+    store().current.load_full()
+}
+
+/// Notified with the new settings after every successful reload.
+pub fn subscribe_changes() -> watch::Receiver<Arc<Settings>> {
+    store().changes.subscribe()
+}
+
+/// Re-read every config layer and swap it in.
+///
+/// Listening ports, storage and SSH settings only apply at startup: they are
+/// carried over from the running config (with a warning if they changed).
+/// An invalid config is rejected and the running one kept.
+pub fn reload_settings() -> Result<Arc<Settings>, Vec<String>> {
+    let mut next = Settings::new().map_err(|e| vec![e.to_string()])?;
+    let current = get_settings();
+
+    let mut restart_only = Vec::new();
+    if next.server.port != current.server.port || next.server.monitor_port != current.server.monitor_port
+        || next.server.tls.enabled != current.server.tls.enabled || next.server.tls.port != current.server.tls.port {
+        restart_only.push("server ports");
+    }
+    if serde_json::to_value(&next.database).ok() != serde_json::to_value(&current.database).ok() {
+        restart_only.push("database");
+    }
+    if serde_json::to_value(&next.storage).ok() != serde_json::to_value(&current.storage).ok() {
+        restart_only.push("storage");
+    }
+    if serde_json::to_value(&next.ssh).ok() != serde_json::to_value(&current.ssh).ok() {
+        restart_only.push("ssh");
+    }
+    if next.log.format != current.log.format || serde_json::to_value(&next.log.otlp).ok() != serde_json::to_value(&current.log.otlp).ok() {
+        restart_only.push("log format/otlp");
+    }
+    if !restart_only.is_empty() {
+        warn!("Config reload: changes to {} need a restart and were ignored", restart_only.join(", "));
+    }
+    next.server.port = current.server.port;
+    next.server.monitor_port = current.server.monitor_port;
+    next.server.tls.enabled = current.server.tls.enabled;
+    next.server.tls.port = current.server.tls.port;
+    next.database = current.database.clone();
+    next.storage = current.storage.clone();
+    next.ssh = current.ssh.clone();
+    next.log.format = current.log.format.clone();
+    next.log.otlp = current.log.otlp.clone();
+
+    let problems = next.validate();
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+
+    let next = Arc::new(next);
+    store().current.store(next.clone());
+    store().changes.send_replace(next.clone());
+    info!("Configuration reloaded");
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::subscribers::EventFilter;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_secrets() {
+        let mut value = json!({
+            "database": { "password": "hunter2", "name": "nc" },
+            "webhook": {
+                "teams_url": "https://teams/secret",
+                "nauticoncept_secret": null,
+                "subscribers": [{ "url": "https://api", "secret": "s", "headers": { "X-Api-Key": "k" } }]
+            },
+            "notifications": { "channels": [{ "url": "https://hooks.slack.com/T/B/X", "smtp": { "password": "p", "host": "smtp" } }] }
+        });
+        redact(&mut value, false);
+
+        assert_eq!(value["database"]["password"], REDACTED);
+        assert_eq!(value["database"]["name"], "nc");
+        assert_eq!(value["webhook"]["teams_url"], REDACTED);
+        assert!(value["webhook"]["nauticoncept_secret"].is_null());
+        assert_eq!(value["webhook"]["subscribers"][0]["url"], REDACTED);
+        assert_eq!(value["webhook"]["subscribers"][0]["secret"], REDACTED);
+        assert_eq!(value["webhook"]["subscribers"][0]["headers"], REDACTED);
+        assert_eq!(value["notifications"]["channels"][0]["url"], REDACTED);
+        assert_eq!(value["notifications"]["channels"][0]["smtp"]["password"], REDACTED);
+        assert_eq!(value["notifications"]["channels"][0]["smtp"]["host"], "smtp");
+    }
+
+    #[test]
+    fn test_app_env_prefixes() {
+        let load = |vars: &[(&str, &str)]| {
+            let vars: config::Map<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            let [legacy_prefix, prefix] = app_env_sources(Some(vars));
+            let config = Config::builder().add_source(legacy_prefix).add_source(prefix).build().unwrap();
+            config.get_int("server.port").ok()
+        };
+        assert_eq!(load(&[("APP__SERVER__PORT", "6001")]), Some(6001));
+        assert_eq!(load(&[("APP_SERVER__PORT", "6002")]), Some(6002));
+        assert_eq!(load(&[("APP__SERVER__PORT", "6001"), ("APP_SERVER__PORT", "6002")]), Some(6002));
+    }
+
+    #[test]
+    fn test_reserved_subscriber_name() {
+        let subscriber = |name: &str| SubscriberSettings {
+            name: name.to_string(),
+            url: "https://api.example.com/hook".to_string(),
+            imeis: vec![],
+            group: None,
+            events: EventFilter::All,
+            headers: HashMap::new(),
+            secret: None,
+        };
+        let mut settings = (*get_settings()).clone();
+        settings.webhook.subscribers = vec![subscriber("fleet")];
+        let baseline = settings.validate();
+
+        settings.webhook.subscribers.push(subscriber(NAUTICONCEPT_SUBSCRIBER));
+        let problems: Vec<String> = settings.validate().into_iter().filter(|p| !baseline.contains(p)).collect();
+        assert_eq!(problems, vec!["webhook subscriber name 'nauticoncept' is reserved for webhook.nauticoncept_url".to_string()]);
+    }
 }
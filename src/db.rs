@@ -1,69 +1,28 @@
 use sqlx::postgres::{PgPoolOptions, PgPool};
-use std::process::{Command, Stdio};
 use std::time::Duration;
 use crate::parser::models::AvlRecord;
-use crate::notifications::TeamsNotificationService;
 use crate::config::get_settings;
+use crate::sink::{DeviceStatus, GeofenceState, NewWebhookJob, SessionSummary, WebhookJob};
+use chrono::{DateTime, Utc};
+use sqlx::Row;
 use serde_json::json;
 use tracing::info;
 
-pub struct SshTunnel {
-    child: std::process::Child,
-}
-
-impl Drop for SshTunnel {
-    fn drop(&mut self) {
-        info!("Stopping SSH tunnel...");
-        let _ = self.child.kill();
-        let _ = self.child.wait();
-    }
-}
-
-pub async fn init_db() -> Result<(PgPool, Option<SshTunnel>), sqlx::Error> {
-    let mut tunnel = None;
+/// Postgres pool (through the SSH tunnel's local port when one is configured).
+///
+/// Connections are opened on demand and checked before use; a query waits at
+/// most `database.acquire_timeout_ms` for one, so an outage fails fast instead
+/// of piling up device tasks.
+pub fn init_db() -> PgPool {
     let settings = get_settings();
 
-    if let Some(user) = &settings.ssh.user {
-        if !user.is_empty() {
-            let host = settings.ssh.host.as_deref().unwrap_or("");
-            let key_path = settings.ssh.key_path.as_deref().unwrap_or("");
-            let local_port = settings.database.port;
-            
-            info!("Starting SSH tunnel to {}@{} on port {}...", user, host, local_port);
-            
-            // ssh -N -L local_port:127.0.0.1:5432 user@host -i key_path
-            let mut cmd = Command::new("ssh");
-            cmd.arg("-N")
-                .arg("-L")
-                .arg(format!("{}:127.0.0.1:5432", local_port))
-                .arg(format!("{}@{}", user, host));
-            
-            if !key_path.is_empty() {
-                cmd.arg("-i").arg(key_path);
-            }
-                
-            let child = cmd.arg("-o").arg("StrictHostKeyChecking=no") 
-                .arg("-o").arg("ExitOnForwardFailure=yes")
-                .stdout(Stdio::null())
-                .stderr(Stdio::inherit()) 
-                .spawn();
-
-            if let Ok(c) = child {
-                tunnel = Some(SshTunnel { child: c });
-            }
-            
-            // Give it a moment to establish
-            tokio::time::sleep(Duration::from_secs(5)).await;
-        }
-    }
-
     let host = &settings.database.host;
     let port = settings.database.port;
     let user = &settings.database.user;
     let password = &settings.database.password;
     let db_name = &settings.database.name;
 
-    info!("Connecting to DB at {}:{}/{} as {}...", host, port, db_name, user);
+    info!("Using DB at {}:{}/{} as {}...", host, port, db_name, user);
 
     let options = sqlx::postgres::PgConnectOptions::new()
         .host(host)
@@ -72,36 +31,277 @@ pub async fn init_db() -> Result<(PgPool, Option<SshTunnel>), sqlx::Error> {
         .password(password)
         .database(db_name);
 
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect_with(options).await?;
-        
-    Ok((pool, tunnel))
+    PgPoolOptions::new()
+        .max_connections(settings.database.pool_size)
+        .acquire_timeout(Duration::from_millis(settings.database.acquire_timeout_ms))
+        .test_before_acquire(true)
+        .connect_lazy_with(options)
 }
 
 pub struct TeltonikaDataRepo;
 
 impl TeltonikaDataRepo {
-    pub async fn save_avl_data(pool: &PgPool, imei: &str, data: &Vec<AvlRecord>, raw: &str, status: &str) {
-        let sql = "INSERT INTO teltonika_data (imei, data, raw, created_at, status) VALUES ($1, $2, $3, $4, $5)";
-        
+    pub const INSERT_DATA_SQL: &'static str = "INSERT INTO teltonika_data (imei, data, raw, created_at, status) VALUES ($1, $2, $3, $4, $5) RETURNING id::bigint";
+
+    /// For a `teltonika_data` table without an `id` column: webhooks then carry `row_id: null`.
+    pub const INSERT_DATA_NO_ID_SQL: &'static str = "INSERT INTO teltonika_data (imei, data, raw, created_at, status) VALUES ($1, $2, $3, $4, $5)";
+
+    /// `teltonika_data` is owned by the consumers, not created here.
+    pub const HAS_ID_COLUMN_SQL: &'static str = "SELECT EXISTS (SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'teltonika_data' AND column_name = 'id')";
+
+    pub const CREATE_STATE_SQL: &'static str = "CREATE TABLE IF NOT EXISTS teltonika_device_state (
+        imei TEXT PRIMARY KEY,
+        data JSONB NOT NULL,
+        recorded_at TIMESTAMPTZ NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL
+    )";
+
+    pub const UPSERT_STATE_SQL: &'static str = "INSERT INTO teltonika_device_state (imei, data, recorded_at, updated_at) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (imei) DO UPDATE SET data = EXCLUDED.data, recorded_at = EXCLUDED.recorded_at, updated_at = EXCLUDED.updated_at
+        WHERE teltonika_device_state.recorded_at <= EXCLUDED.recorded_at";
+
+    pub async fn save_avl_data(pool: &PgPool, imei: &str, data: &[AvlRecord], raw: &str, status: &str, returning_id: bool) -> Result<Option<i64>, sqlx::Error> {
         let json_data = json!(data);
+        let sql = if returning_id { Self::INSERT_DATA_SQL } else { Self::INSERT_DATA_NO_ID_SQL };
         
-        let res = sqlx::query(sql)
+        let query = sqlx::query(sql)
             .bind(imei)
             .bind(&json_data)
             .bind(raw)
             .bind(chrono::Utc::now())
-            .bind(status)
-            .execute(pool).await;
-            
-        if let Err(e) = res {
-             let msg = format!("{:?}", e);
-             TeamsNotificationService::sql_error(sql, &msg).await;
+            .bind(status);
+        if returning_id {
+            query.fetch_one(pool).await.and_then(|row| row.try_get(0)).map(Some)
+        } else {
+            query.execute(pool).await.map(|_| None)
         }
     }
 
+    pub async fn has_id_column(pool: &PgPool) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>(Self::HAS_ID_COLUMN_SQL).fetch_one(pool).await
+    }
+
+    pub async fn upsert_device_state(pool: &PgPool, imei: &str, record: &AvlRecord) -> Result<(), sqlx::Error> {
+        sqlx::query(Self::UPSERT_STATE_SQL)
+            .bind(imei)
+            .bind(json!(record))
+            .bind(record.timestamp)
+            .bind(chrono::Utc::now())
+            .execute(pool).await
+            .map(|_| ())
+    }
+
+    pub const CREATE_GEOFENCE_STATE_SQL: &'static str = "CREATE TABLE IF NOT EXISTS teltonika_geofence_state (
+        imei TEXT NOT NULL,
+        geofence TEXT NOT NULL,
+        inside BOOLEAN NOT NULL,
+        recorded_at TIMESTAMPTZ NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY (imei, geofence)
+    )";
+
+    pub const UPSERT_GEOFENCE_STATE_SQL: &'static str = "INSERT INTO teltonika_geofence_state (imei, geofence, inside, recorded_at, updated_at) VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (imei, geofence) DO UPDATE SET inside = EXCLUDED.inside, recorded_at = EXCLUDED.recorded_at, updated_at = EXCLUDED.updated_at
+        WHERE teltonika_geofence_state.recorded_at <= EXCLUDED.recorded_at";
+
+    pub async fn upsert_geofence_state(pool: &PgPool, state: &GeofenceState) -> Result<(), sqlx::Error> {
+        sqlx::query(Self::UPSERT_GEOFENCE_STATE_SQL)
+            .bind(&state.imei)
+            .bind(&state.geofence)
+            .bind(state.inside)
+            .bind(state.recorded_at)
+            .bind(chrono::Utc::now())
+            .execute(pool).await
+            .map(|_| ())
+    }
+
+    pub const GEOFENCE_STATES_SQL: &'static str = "SELECT imei, geofence, inside, recorded_at FROM teltonika_geofence_state";
+
+    pub async fn geofence_states(pool: &PgPool) -> Result<Vec<GeofenceState>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (String, String, bool, DateTime<Utc>)>(Self::GEOFENCE_STATES_SQL)
+            .fetch_all(pool).await?;
+        Ok(rows.into_iter().map(|(imei, geofence, inside, recorded_at)| GeofenceState { imei, geofence, inside, recorded_at }).collect())
+    }
+
+    pub const LAST_SEEN_SQL: &'static str = "SELECT imei, updated_at FROM teltonika_device_state";
+
+    pub async fn last_seen(pool: &PgPool) -> Result<Vec<(String, DateTime<Utc>)>, sqlx::Error> {
+        sqlx::query_as::<_, (String, DateTime<Utc>)>(Self::LAST_SEEN_SQL)
+            .fetch_all(pool).await
+    }
+
     pub async fn check_health(pool: &PgPool) -> Result<(), sqlx::Error> {
         sqlx::query("SELECT 1").execute(pool).await.map(|_| ())
     }
 }
+
+pub struct WebhookQueueRepo;
+
+impl WebhookQueueRepo {
+    pub const CREATE_QUEUE_SQL: &'static str = "CREATE TABLE IF NOT EXISTS webhook_queue (
+        id BIGSERIAL PRIMARY KEY,
+        subscriber TEXT NOT NULL,
+        url TEXT NOT NULL,
+        body JSONB NOT NULL,
+        attempts INT NOT NULL DEFAULT 0,
+        next_attempt_at TIMESTAMPTZ NOT NULL,
+        last_error TEXT,
+        created_at TIMESTAMPTZ NOT NULL
+    )";
+
+    pub const CREATE_DEAD_LETTER_SQL: &'static str = "CREATE TABLE IF NOT EXISTS webhook_dead_letter (
+        id BIGINT PRIMARY KEY,
+        subscriber TEXT NOT NULL,
+        url TEXT NOT NULL,
+        body JSONB NOT NULL,
+        attempts INT NOT NULL,
+        last_error TEXT,
+        created_at TIMESTAMPTZ NOT NULL,
+        failed_at TIMESTAMPTZ NOT NULL
+    )";
+
+    pub const ENQUEUE_SQL: &'static str = "INSERT INTO webhook_queue (subscriber, url, body, attempts, next_attempt_at, created_at) VALUES ($1, $2, $3::jsonb, 0, $4, $4) RETURNING id";
+
+    pub const DUE_SQL: &'static str = "SELECT id, subscriber, body::text AS body, attempts, created_at FROM webhook_queue WHERE next_attempt_at <= $1 ORDER BY next_attempt_at, id LIMIT $2";
+
+    pub const DELETE_SQL: &'static str = "DELETE FROM webhook_queue WHERE id = $1";
+
+    pub const RESCHEDULE_SQL: &'static str = "UPDATE webhook_queue SET attempts = $2, next_attempt_at = $3, last_error = $4 WHERE id = $1";
+
+    pub const DEAD_LETTER_SQL: &'static str = "WITH moved AS (DELETE FROM webhook_queue WHERE id = $1 RETURNING id, subscriber, url, body, created_at)
+        INSERT INTO webhook_dead_letter (id, subscriber, url, body, attempts, last_error, created_at, failed_at)
+        SELECT id, subscriber, url, body, $2, $3, created_at, $4 FROM moved";
+
+    pub const COUNT_SQL: &'static str = "SELECT COUNT(*) FROM webhook_queue";
+
+    pub async fn enqueue(pool: &PgPool, job: &NewWebhookJob) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(Self::ENQUEUE_SQL)
+            .bind(&job.subscriber)
+            .bind(&job.url)
+            .bind(&job.body)
+            .bind(Utc::now())
+            .fetch_one(pool).await
+    }
+
+    pub async fn due(pool: &PgPool, now: DateTime<Utc>, limit: i64) -> Result<Vec<WebhookJob>, sqlx::Error> {
+        let rows = sqlx::query(Self::DUE_SQL)
+            .bind(now)
+            .bind(limit)
+            .fetch_all(pool).await?;
+
+        rows.iter().map(|row| Ok(WebhookJob {
+            id: row.try_get("id")?,
+            subscriber: row.try_get("subscriber")?,
+            body: row.try_get("body")?,
+            attempts: row.try_get("attempts")?,
+            created_at: row.try_get("created_at")?,
+        })).collect()
+    }
+
+    pub async fn delete(pool: &PgPool, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(Self::DELETE_SQL).bind(id).execute(pool).await.map(|_| ())
+    }
+
+    pub async fn reschedule(pool: &PgPool, id: i64, attempts: i32, next_attempt_at: DateTime<Utc>, last_error: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(Self::RESCHEDULE_SQL)
+            .bind(id)
+            .bind(attempts)
+            .bind(next_attempt_at)
+            .bind(last_error)
+            .execute(pool).await
+            .map(|_| ())
+    }
+
+    pub async fn dead_letter(pool: &PgPool, id: i64, attempts: i32, last_error: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(Self::DEAD_LETTER_SQL)
+            .bind(id)
+            .bind(attempts)
+            .bind(last_error)
+            .bind(Utc::now())
+            .execute(pool).await
+            .map(|_| ())
+    }
+
+    pub async fn count(pool: &PgPool) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(Self::COUNT_SQL).fetch_one(pool).await
+    }
+}
+
+pub struct DeviceRepo;
+
+impl DeviceRepo {
+    pub const CREATE_DEVICES_SQL: &'static str = "CREATE TABLE IF NOT EXISTS teltonika_devices (
+        imei TEXT PRIMARY KEY,
+        status TEXT NOT NULL,
+        label TEXT,
+        created_at TIMESTAMPTZ NOT NULL
+    )";
+
+    pub const STATUS_SQL: &'static str = "SELECT status FROM teltonika_devices WHERE imei = $1";
+
+    pub const ENROLL_SQL: &'static str = "INSERT INTO teltonika_devices (imei, status, created_at) VALUES ($1, $2, $3) ON CONFLICT (imei) DO NOTHING";
+
+    pub async fn status(pool: &PgPool, imei: &str) -> Result<Option<DeviceStatus>, sqlx::Error> {
+        let status = sqlx::query_scalar::<_, String>(Self::STATUS_SQL)
+            .bind(imei)
+            .fetch_optional(pool).await?;
+        Ok(status.as_deref().map(DeviceStatus::parse))
+    }
+
+    pub async fn enroll(pool: &PgPool, imei: &str, status: DeviceStatus) -> Result<bool, sqlx::Error> {
+        sqlx::query(Self::ENROLL_SQL)
+            .bind(imei)
+            .bind(status.as_str())
+            .bind(Utc::now())
+            .execute(pool).await
+            .map(|res| res.rows_affected() > 0)
+    }
+}
+
+pub struct DeviceSessionRepo;
+
+impl DeviceSessionRepo {
+    pub const CREATE_SESSIONS_SQL: &'static str = "CREATE TABLE IF NOT EXISTS device_sessions (
+        id BIGSERIAL PRIMARY KEY,
+        imei TEXT NOT NULL,
+        peer_addr TEXT NOT NULL,
+        connected_at TIMESTAMPTZ NOT NULL,
+        disconnected_at TIMESTAMPTZ,
+        disconnect_reason TEXT,
+        packets BIGINT NOT NULL DEFAULT 0,
+        records BIGINT NOT NULL DEFAULT 0,
+        bytes_received BIGINT NOT NULL DEFAULT 0,
+        bytes_sent BIGINT NOT NULL DEFAULT 0,
+        duration_ms BIGINT
+    )";
+
+    pub const CREATE_SESSIONS_INDEX_SQL: &'static str = "CREATE INDEX IF NOT EXISTS device_sessions_imei_idx ON device_sessions (imei, connected_at DESC)";
+
+    pub const OPEN_SQL: &'static str = "INSERT INTO device_sessions (imei, peer_addr, connected_at) VALUES ($1, $2, $3) RETURNING id";
+
+    pub const CLOSE_SQL: &'static str = "UPDATE device_sessions SET disconnected_at = $2, disconnect_reason = $3, packets = $4, records = $5,
+        bytes_received = $6, bytes_sent = $7, duration_ms = $8 WHERE id = $1";
+
+    pub async fn open(pool: &PgPool, imei: &str, peer: &str, connected_at: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(Self::OPEN_SQL)
+            .bind(imei)
+            .bind(peer)
+            .bind(connected_at)
+            .fetch_one(pool).await
+    }
+
+    pub async fn close(pool: &PgPool, id: i64, summary: &SessionSummary) -> Result<(), sqlx::Error> {
+        sqlx::query(Self::CLOSE_SQL)
+            .bind(id)
+            .bind(summary.disconnected_at)
+            .bind(&summary.reason)
+            .bind(summary.packets)
+            .bind(summary.records)
+            .bind(summary.bytes_received)
+            .bind(summary.bytes_sent)
+            .bind(summary.duration_ms)
+            .execute(pool).await
+            .map(|_| ())
+    }
+}
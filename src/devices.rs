@@ -0,0 +1,140 @@
+use arc_swap::ArcSwap;
+use std::collections::HashSet;
+use std::sync::{Arc, OnceLock};
+use tracing::{error, info, warn};
+
+use crate::config::DevicesSettings;
+use crate::sink::{DeviceStatus, DeviceStore};
+
+/// Outcome of the IMEI check at the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    /// Reason, also the `result` label of `device_auth_total`.
+    Rejected(&'static str),
+}
+
+static ALLOWLIST: OnceLock<ArcSwap<HashSet<String>>> = OnceLock::new();
+
+fn allowlist() -> &'static ArcSwap<HashSet<String>> {
+    ALLOWLIST.get_or_init(|| ArcSwap::from_pointee(HashSet::new()))
+}
+
+fn parse_allowlist(text: &str) -> HashSet<String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|imei| !imei.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// (Re)load `devices.allowlist_file`. A file that cannot be read keeps the previous list.
+pub fn load_allowlist(settings: &DevicesSettings) {
+    if settings.allowlist_file.is_empty() {
+        allowlist().store(Arc::new(HashSet::new()));
+        return;
+    }
+    match std::fs::read_to_string(&settings.allowlist_file) {
+        Ok(text) => {
+            let imeis = parse_allowlist(&text);
+            info!("Loaded {} IMEI(s) from {}", imeis.len(), settings.allowlist_file);
+            allowlist().store(Arc::new(imeis));
+        }
+        Err(e) => error!("Cannot read devices.allowlist_file '{}': {}", settings.allowlist_file, e),
+    }
+}
+
+/// Decides which IMEIs may send data: the allowlist file first, then the
+/// `teltonika_devices` table of the storage backend.
+pub struct DeviceRegistry {
+    store: Arc<dyn DeviceStore>,
+}
+
+impl DeviceRegistry {
+    pub fn new(store: Arc<dyn DeviceStore>) -> Arc<Self> {
+        Arc::new(DeviceRegistry { store })
+    }
+
+    pub async fn authorize(&self, settings: &DevicesSettings, imei: &str) -> Admission {
+        let admission = self.check(settings, imei).await;
+        let result = match admission {
+            Admission::Allowed => "allowed",
+            Admission::Rejected(reason) => reason,
+        };
+        metrics::counter!("device_auth_total", "result" => result).increment(1);
+        admission
+    }
+
+    async fn check(&self, settings: &DevicesSettings, imei: &str) -> Admission {
+        if !settings.enforce || allowlist().load().contains(imei) {
+            return Admission::Allowed;
+        }
+
+        match self.store.device_status(imei).await {
+            Ok(Some(DeviceStatus::Active)) => Admission::Allowed,
+            Ok(Some(DeviceStatus::Quarantine)) => Admission::Rejected("quarantined"),
+            Ok(Some(DeviceStatus::Blocked)) => Admission::Rejected("blocked"),
+            Ok(None) => {
+                if settings.auto_enroll {
+                    match self.store.enroll(imei, DeviceStatus::Quarantine).await {
+                        Ok(true) => {
+                            info!("Enrolled unknown IMEI {} in quarantine", imei);
+                            metrics::counter!("devices_enrolled_total").increment(1);
+                        }
+                        Ok(false) => {}
+                        Err(e) => warn!("Failed to enroll IMEI {}: {}", imei, e),
+                    }
+                }
+                Admission::Rejected("unknown")
+            }
+            Err(e) if settings.fail_open => {
+                warn!("Device registry unavailable, accepting IMEI {}: {}", imei, e);
+                Admission::Allowed
+            }
+            Err(e) => {
+                warn!("Device registry unavailable, rejecting IMEI {}: {}", imei, e);
+                Admission::Rejected("registry_unavailable")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::DuplicatePolicy;
+    use crate::sink::MemorySink;
+
+    fn enforcing(auto_enroll: bool) -> DevicesSettings {
+        DevicesSettings { enforce: true, allowlist_file: String::new(), auto_enroll, fail_open: true, allow_imeisv: false, duplicate_sessions: DuplicatePolicy::CloseOld }
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let sink = Arc::new(MemorySink::new());
+        let registry = DeviceRegistry::new(sink.clone());
+
+        sink.enroll("356307042441013", DeviceStatus::Active).await.unwrap();
+        sink.enroll("356307042441021", DeviceStatus::Blocked).await.unwrap();
+
+        assert_eq!(registry.authorize(&enforcing(false), "356307042441013").await, Admission::Allowed);
+        assert_eq!(registry.authorize(&enforcing(false), "356307042441021").await, Admission::Rejected("blocked"));
+        assert_eq!(registry.authorize(&enforcing(false), "356307042441039").await, Admission::Rejected("unknown"));
+        assert_eq!(sink.device_status("356307042441039").await.unwrap(), None);
+
+        // Auto-enrolled devices stay rejected until activated
+        assert_eq!(registry.authorize(&enforcing(true), "356307042441039").await, Admission::Rejected("unknown"));
+        assert_eq!(sink.device_status("356307042441039").await.unwrap(), Some(DeviceStatus::Quarantine));
+        assert_eq!(registry.authorize(&enforcing(true), "356307042441039").await, Admission::Rejected("quarantined"));
+
+        let open = DevicesSettings { enforce: false, ..enforcing(false) };
+        assert_eq!(registry.authorize(&open, "356307042441039").await, Admission::Allowed);
+    }
+
+    #[test]
+    fn test_parse_allowlist() {
+        let imeis = parse_allowlist("# boats\n356307042441013\n\n  356307042441021  # spare\n");
+        assert_eq!(imeis.len(), 2);
+        assert!(imeis.contains("356307042441021"));
+    }
+}
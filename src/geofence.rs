@@ -0,0 +1,367 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::config::{get_settings, Settings};
+use crate::notifications::{NotificationService, Severity};
+use crate::parser::models::{AvlRecord, TeltonikaGps};
+use crate::sink::{GeofenceState, NewWebhookJob, TelemetrySink};
+use crate::utils::backoff_delay;
+use crate::webhook::{jobs_for_geofence, GeofencePayload, WEBHOOK_SCHEMA_VERSION};
+
+/// Mean Earth radius used for circle distances.
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct CircleSettings {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_m: f64,
+}
+
+/// One zone from `[[geofences]]`: a circle or a polygon, assigned to IMEIs and/or a group.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeofenceSettings {
+    pub name: String,
+    #[serde(default)]
+    pub circle: Option<CircleSettings>,
+    /// At least 3 vertices, in order; the polygon is closed implicitly.
+    #[serde(default)]
+    pub polygon: Vec<GeoPoint>,
+    #[serde(default)]
+    pub imeis: Vec<String>,
+    /// IMEIs of this group from `[groups]`.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Also send enter/exit to the alert channels.
+    #[serde(default)]
+    pub notify: bool,
+}
+
+impl GeofenceSettings {
+    pub fn applies_to(&self, imei: &str, groups: &HashMap<String, Vec<String>>) -> bool {
+        self.imeis.iter().any(|i| i == imei)
+            || self.group.as_ref().is_some_and(|group| groups.get(group).is_some_and(|members| members.iter().any(|i| i == imei)))
+    }
+
+    pub fn contains(&self, point: GeoPoint) -> bool {
+        match &self.circle {
+            Some(circle) => distance_m(GeoPoint { latitude: circle.latitude, longitude: circle.longitude }, point) <= circle.radius_m,
+            None => in_polygon(&self.polygon, point),
+        }
+    }
+
+    /// Config problems of this zone, for `Settings::validate`.
+    pub fn problems(&self, groups: &HashMap<String, Vec<String>>) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut points: Vec<GeoPoint> = self.polygon.clone();
+        match (&self.circle, self.polygon.len()) {
+            (Some(circle), 0) => {
+                if circle.radius_m <= 0.0 {
+                    problems.push(format!("geofence '{}': radius_m must be greater than 0", self.name));
+                }
+                points.push(GeoPoint { latitude: circle.latitude, longitude: circle.longitude });
+            }
+            (None, n) if n >= 3 => {}
+            _ => problems.push(format!("geofence '{}' needs either a circle or a polygon of at least 3 points", self.name)),
+        }
+        if points.iter().any(|p| !(-90.0..=90.0).contains(&p.latitude) || !(-180.0..=180.0).contains(&p.longitude)) {
+            problems.push(format!("geofence '{}' has coordinates out of range", self.name));
+        }
+        if self.imeis.is_empty() && self.group.is_none() {
+            problems.push(format!("geofence '{}' is not assigned to any imeis or group", self.name));
+        }
+        if let Some(group) = &self.group {
+            if !groups.contains_key(group) {
+                problems.push(format!("geofence '{}' uses unknown group '{}'", self.name, group));
+            }
+        }
+        problems
+    }
+}
+
+/// Great-circle (haversine) distance.
+fn distance_m(a: GeoPoint, b: GeoPoint) -> f64 {
+    let (lat1, lat2) = (a.latitude.to_radians(), b.latitude.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b.longitude - a.longitude).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+/// Ray casting on plain lat/lon, fine for harbour-sized zones away from the antimeridian.
+fn in_polygon(polygon: &[GeoPoint], point: GeoPoint) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[j];
+        if (a.latitude > point.latitude) != (b.latitude > point.latitude)
+            && point.longitude < (b.longitude - a.longitude) * (point.latitude - a.latitude) / (b.latitude - a.latitude) + a.longitude
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// A device crossed a zone boundary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crossing {
+    pub geofence: String,
+    pub entered: bool,
+    pub position: GeoPoint,
+    pub timestamp: DateTime<Utc>,
+    pub notify: bool,
+}
+
+/// Outcome of one `Geofences::check`.
+#[derive(Debug, Default)]
+pub struct Check {
+    pub crossings: Vec<Crossing>,
+    /// Final state of every zone the fixes were checked against, to store.
+    pub states: Vec<GeofenceState>,
+}
+
+struct ZoneState {
+    inside: bool,
+    /// Fix the state comes from; older (resent) records are ignored.
+    at: DateTime<Utc>,
+}
+
+/// Inside/outside state per IMEI and zone, kept across packets and connections
+/// and seeded from `teltonika_geofence_state` at startup.
+pub struct Geofences {
+    states: OnceLock<Mutex<HashMap<String, HashMap<String, ZoneState>>>>,
+}
+
+pub static GEOFENCES: Geofences = Geofences::new();
+
+impl Geofences {
+    pub const fn new() -> Self {
+        Geofences { states: OnceLock::new() }
+    }
+
+    fn states(&self) -> &Mutex<HashMap<String, HashMap<String, ZoneState>>> {
+        self.states.get_or_init(Default::default)
+    }
+
+    /// States stored before the restart. Zones checked since startup keep
+    /// their live state.
+    fn seed(&self, stored: Vec<GeofenceState>) {
+        let mut states = self.states().lock().unwrap();
+        for state in stored {
+            states.entry(state.imei).or_default()
+                .entry(state.geofence)
+                .or_insert(ZoneState { inside: state.inside, at: state.recorded_at });
+        }
+    }
+
+    /// Run the records' fixes, oldest first, through the device's zones. The
+    /// first fix for a zone without a stored state only sets it, it is not reported.
+    pub fn check(&self, settings: &Settings, imei: &str, records: &[AvlRecord]) -> Check {
+        let zones: Vec<&GeofenceSettings> = settings.geofences.iter().filter(|z| z.applies_to(imei, &settings.groups)).collect();
+        let mut states = self.states().lock().unwrap();
+        if zones.is_empty() {
+            states.remove(imei);
+            return Check::default();
+        }
+
+        let device = states.entry(imei.to_string()).or_default();
+        // Zones removed or reassigned by a reload
+        device.retain(|name, _| zones.iter().any(|z| &z.name == name));
+
+        let mut fixes: Vec<&AvlRecord> = records.iter().filter(|r| has_fix(&r.gps)).collect();
+        fixes.sort_by_key(|r| r.timestamp);
+
+        let mut crossings = Vec::new();
+        let mut checked: Vec<&str> = Vec::new();
+        for record in fixes {
+            let position = GeoPoint { latitude: record.gps.latitude, longitude: record.gps.longitude };
+            for zone in &zones {
+                let inside = zone.contains(position);
+                match device.get_mut(&zone.name) {
+                    Some(state) if record.timestamp < state.at => continue,
+                    Some(state) => {
+                        state.at = record.timestamp;
+                        if state.inside != inside {
+                            state.inside = inside;
+                            crossings.push(Crossing { geofence: zone.name.clone(), entered: inside, position, timestamp: record.timestamp, notify: zone.notify });
+                        }
+                    }
+                    None => {
+                        device.insert(zone.name.clone(), ZoneState { inside, at: record.timestamp });
+                    }
+                }
+                if !checked.contains(&zone.name.as_str()) {
+                    checked.push(&zone.name);
+                }
+            }
+        }
+
+        let states = checked.into_iter().map(|name| GeofenceState {
+            imei: imei.to_string(),
+            geofence: name.to_string(),
+            inside: device[name].inside,
+            recorded_at: device[name].at,
+        }).collect();
+        Check { crossings, states }
+    }
+
+    /// Seed from the stored states, retrying with backoff while the database is unreachable.
+    pub fn spawn_seeder(&'static self, sink: Arc<dyn TelemetrySink>, shutdown: CancellationToken) {
+        tokio::spawn(async move {
+            let mut attempts = 0;
+            loop {
+                attempts += 1;
+                match sink.geofence_states().await {
+                    Ok(stored) => {
+                        info!("Geofences seeded with {} zone state(s)", stored.len());
+                        self.seed(stored);
+                        return;
+                    }
+                    Err(e) => warn!("Cannot load geofence states: {}", e),
+                }
+                let delay = backoff_delay(attempts, 1000, get_settings().database.connect_backoff_max_ms);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        });
+    }
+}
+
+/// Teltonika reports 0/0 while it has no position.
+fn has_fix(gps: &TeltonikaGps) -> bool {
+    gps.latitude != 0.0 || gps.longitude != 0.0
+}
+
+/// Count and alert a crossing, and build the jobs for `geofence_events` subscribers.
+pub fn announce(settings: &Settings, imei: &str, received_at: DateTime<Utc>, crossing: &Crossing) -> Vec<NewWebhookJob> {
+    let (event, verb) = if crossing.entered { ("geofence_enter", "entered") } else { ("geofence_exit", "left") };
+    tracing::info!("📍 IMEI {} {} geofence '{}' at {:.6},{:.6}", imei, verb, crossing.geofence, crossing.position.latitude, crossing.position.longitude);
+    metrics::counter!("geofence_events_total", "event" => event).increment(1);
+    if crossing.notify {
+        NotificationService::notify(Severity::Info, &format!("📍 Geofence {}", crossing.geofence),
+            &format!("IMEI {} {} '{}' at {} ({:.6}, {:.6}).", imei, verb, crossing.geofence, crossing.timestamp, crossing.position.latitude, crossing.position.longitude));
+    }
+
+    let payload = GeofencePayload {
+        schema_version: WEBHOOK_SCHEMA_VERSION,
+        event: event.to_string(),
+        imei: imei.to_string(),
+        geofence: crossing.geofence.clone(),
+        latitude: crossing.position.latitude,
+        longitude: crossing.position.longitude,
+        timestamp: crossing.timestamp,
+        received_at,
+    };
+    jobs_for_geofence(settings, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_settings;
+    use crate::parser::models::IoGroup;
+    use crate::sink::MemorySink;
+
+    const BOAT: &str = "356307042441013";
+
+    fn fix(secs: i64, latitude: f64, longitude: f64) -> AvlRecord {
+        AvlRecord {
+            timestamp: DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+            priority: 0,
+            gps: TeltonikaGps { longitude, latitude, altitude: 0, angle: 0, satellites: 8, speed: 0 },
+            event_id: 0,
+            io_groups: IoGroup { n1: vec![], n2: vec![], n4: vec![], n8: vec![], nx: vec![] },
+            io_elements: vec![],
+            properties_count: 0,
+        }
+    }
+
+    fn state(geofence: &str, inside: bool, secs: i64) -> GeofenceState {
+        GeofenceState { imei: BOAT.to_string(), geofence: geofence.to_string(), inside, recorded_at: fix(secs, 0.0, 0.0).timestamp }
+    }
+
+    fn zone(name: &str, circle: Option<CircleSettings>, polygon: Vec<GeoPoint>) -> GeofenceSettings {
+        GeofenceSettings { name: name.to_string(), circle, polygon, imeis: vec![BOAT.to_string()], group: None, notify: false }
+    }
+
+    #[test]
+    fn test_shapes() {
+        let berth = zone("berth", Some(CircleSettings { latitude: 43.2951, longitude: 5.3740, radius_m: 30.0 }), vec![]);
+        assert!(berth.contains(GeoPoint { latitude: 43.2952, longitude: 5.3741 }));
+        assert!(!berth.contains(GeoPoint { latitude: 43.2960, longitude: 5.3740 })); // ~100 m north
+
+        let square = [(43.29, 5.36), (43.30, 5.36), (43.30, 5.38), (43.29, 5.38)];
+        let harbour = zone("harbour", None, square.iter().map(|&(latitude, longitude)| GeoPoint { latitude, longitude }).collect());
+        assert!(harbour.contains(GeoPoint { latitude: 43.295, longitude: 5.37 }));
+        assert!(!harbour.contains(GeoPoint { latitude: 43.305, longitude: 5.37 }));
+        assert!(harbour.problems(&HashMap::new()).is_empty());
+        assert!(!zone("line", None, harbour.polygon[..2].to_vec()).problems(&HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_crossings() {
+        let mut settings = (*get_settings()).clone();
+        settings.geofences = vec![zone("berth", Some(CircleSettings { latitude: 43.2951, longitude: 5.3740, radius_m: 30.0 }), vec![])];
+        let geofences = Geofences::new();
+
+        // First fix only sets the state; no position (0/0) is skipped
+        let check = geofences.check(&settings, BOAT, &[fix(0, 43.2951, 5.3740), fix(10, 0.0, 0.0)]);
+        assert!(check.crossings.is_empty());
+        assert_eq!(check.states, [state("berth", true, 0)]);
+
+        // Out of order within a packet: sorted by time, so one exit
+        let check = geofences.check(&settings, BOAT, &[fix(30, 43.2990, 5.3740), fix(20, 43.2951, 5.3741)]);
+        assert_eq!(check.crossings.len(), 1);
+        assert!(!check.crossings[0].entered);
+        assert_eq!(check.crossings[0].timestamp, fix(30, 0.0, 0.0).timestamp);
+        assert_eq!(check.states, [state("berth", false, 30)]);
+
+        // A resent older record does not flip the state, nor is it stored
+        let check = geofences.check(&settings, BOAT, &[fix(25, 43.2951, 5.3740)]);
+        assert!(check.crossings.is_empty() && check.states.is_empty());
+        let check = geofences.check(&settings, BOAT, &[fix(40, 43.2951, 5.3740)]);
+        assert_eq!((check.crossings.len(), check.crossings[0].entered), (1, true));
+
+        // Other devices are not assigned to the zone
+        assert!(geofences.check(&settings, "356307042441021", &[fix(50, 43.2990, 5.3740)]).crossings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_seeded_from_the_sink() {
+        let mut settings = (*get_settings()).clone();
+        settings.geofences = vec![
+            zone("berth", Some(CircleSettings { latitude: 43.2951, longitude: 5.3740, radius_m: 30.0 }), vec![]),
+            zone("harbour", Some(CircleSettings { latitude: 43.2951, longitude: 5.3740, radius_m: 3000.0 }), vec![]),
+        ];
+        let sink = MemorySink::new();
+        sink.upsert_geofence_state(&state("berth", true, 0)).await.unwrap();
+        let geofences = Geofences::new();
+        geofences.seed(sink.geofence_states().await.unwrap());
+
+        // The boat was at its berth before the restart: the first fix after it is an exit.
+        // The harbour has no stored state, so that fix only records it.
+        let check = geofences.check(&settings, BOAT, &[fix(20, 43.2990, 5.3740)]);
+        assert_eq!(check.crossings.len(), 1);
+        assert_eq!((check.crossings[0].geofence.as_str(), check.crossings[0].entered), ("berth", false));
+        assert_eq!(check.states.len(), 2);
+
+        // A state checked since startup wins over a stored one
+        sink.upsert_geofence_state(&state("harbour", false, 0)).await.unwrap();
+        geofences.seed(sink.geofence_states().await.unwrap());
+        assert!(geofences.check(&settings, BOAT, &[fix(30, 43.2990, 5.3740)]).crossings.is_empty());
+    }
+}
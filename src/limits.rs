@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::config::{get_settings, LimitSettings};
+use crate::notifications::{NotificationService, Severity};
+
+/// How often idle per-IP and per-IMEI entries are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Why a new connection was refused at accept time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    Banned,
+    TooManyConnections,
+}
+
+impl Refusal {
+    /// `reason` label of `connections_rejected_total`.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Refusal::Banned => "banned",
+            Refusal::TooManyConnections => "ip_limit",
+        }
+    }
+}
+
+#[derive(Default)]
+struct IpState {
+    connections: usize,
+    failures: u32,
+    failures_since: Option<Instant>,
+    banned_until: Option<Instant>,
+}
+
+impl IpState {
+    fn banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+
+    fn idle(&self, now: Instant, failure_window: Duration) -> bool {
+        self.connections == 0 && !self.banned(now)
+            && self.failures_since.is_none_or(|since| now.duration_since(since) > failure_window)
+    }
+}
+
+/// Token bucket holding up to one minute of `max_packets_per_minute`.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Abuse protection on top of the global `max_connections` semaphore:
+/// per-IP connection caps, temporary bans after repeated handshake failures
+/// and per-IMEI packet rates. Limits are read from `[limits]` on every use.
+pub struct Limits {
+    ips: OnceLock<Mutex<HashMap<IpAddr, IpState>>>,
+    packets: OnceLock<Mutex<HashMap<String, Bucket>>>,
+}
+
+pub static LIMITS: Limits = Limits::new();
+
+/// Per-IP connection slot, released when the connection task ends.
+pub struct IpSlot {
+    limits: &'static Limits,
+    ip: IpAddr,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        if let Some(state) = self.limits.ips().lock().unwrap().get_mut(&self.ip) {
+            state.connections = state.connections.saturating_sub(1);
+        }
+    }
+}
+
+impl Limits {
+    pub const fn new() -> Self {
+        Limits { ips: OnceLock::new(), packets: OnceLock::new() }
+    }
+
+    fn ips(&self) -> &Mutex<HashMap<IpAddr, IpState>> {
+        self.ips.get_or_init(Default::default)
+    }
+
+    fn packets(&self) -> &Mutex<HashMap<String, Bucket>> {
+        self.packets.get_or_init(Default::default)
+    }
+
+    /// Check a newly accepted connection against bans and the per-IP cap.
+    pub fn admit(&'static self, ip: IpAddr, settings: &LimitSettings) -> Result<IpSlot, Refusal> {
+        let refusal = self.check_admit(ip, settings);
+        if let Err(refusal) = refusal {
+            metrics::counter!("connections_rejected_total", "reason" => refusal.label()).increment(1);
+            if refusal == Refusal::TooManyConnections {
+                NotificationService::notify(Severity::Warning, "⚠️ Per-IP connection limit reached",
+                    &format!("{} has {} open connections (limits.max_connections_per_ip)", ip, settings.max_connections_per_ip));
+            }
+        }
+        refusal.map(|()| IpSlot { limits: self, ip })
+    }
+
+    fn check_admit(&self, ip: IpAddr, settings: &LimitSettings) -> Result<(), Refusal> {
+        let exempt = settings.is_exempt(ip);
+        let mut ips = self.ips().lock().unwrap();
+        let state = ips.entry(ip).or_default();
+        if !exempt {
+            if state.banned(Instant::now()) {
+                return Err(Refusal::Banned);
+            }
+            if settings.max_connections_per_ip > 0 && state.connections >= settings.max_connections_per_ip {
+                return Err(Refusal::TooManyConnections);
+            }
+        }
+        state.connections += 1;
+        Ok(())
+    }
+
+    /// A connection from `ip` ended without completing the handshake. Bans the
+    /// IP for `ban_secs` once `handshake_failures_before_ban` is reached within
+    /// `handshake_failure_window_secs`.
+    pub fn handshake_failed(&self, ip: IpAddr, settings: &LimitSettings) {
+        if settings.handshake_failures_before_ban == 0 || settings.is_exempt(ip) {
+            return;
+        }
+        let now = Instant::now();
+        let mut ips = self.ips().lock().unwrap();
+        let state = ips.entry(ip).or_default();
+        let window = Duration::from_secs(settings.handshake_failure_window_secs);
+        if state.failures_since.is_none_or(|since| now.duration_since(since) > window) {
+            state.failures = 0;
+            state.failures_since = Some(now);
+        }
+        state.failures += 1;
+        if state.failures < settings.handshake_failures_before_ban {
+            return;
+        }
+
+        let failures = state.failures;
+        state.failures = 0;
+        state.failures_since = None;
+        state.banned_until = Some(now + Duration::from_secs(settings.ban_secs));
+        let banned = ips.values().filter(|s| s.banned(now)).count();
+        drop(ips);
+
+        warn!("🚫 Banning {} for {}s after {} failed handshakes", ip, settings.ban_secs, failures);
+        metrics::counter!("ip_bans_total").increment(1);
+        metrics::gauge!("banned_ips").set(banned as f64);
+        NotificationService::notify(Severity::Warning, "🚫 IP temporarily banned",
+            &format!("{} failed the handshake {} times within {}s; refused for {}s.", ip, failures, settings.handshake_failure_window_secs, settings.ban_secs));
+    }
+
+    /// Take one packet from the IMEI's budget; false when it is exhausted.
+    pub fn allow_packet(&self, imei: &str, settings: &LimitSettings) -> bool {
+        if settings.max_packets_per_minute == 0 {
+            return true;
+        }
+        let capacity = settings.max_packets_per_minute as f64;
+        let now = Instant::now();
+        let mut packets = self.packets().lock().unwrap();
+        let bucket = packets.entry(imei.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * capacity / 60.0;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            drop(packets);
+            metrics::counter!("rate_limited_packets_total").increment(1);
+            NotificationService::notify(Severity::Warning, "⚠️ Device rate limited",
+                &format!("IMEI {} sends more than {} packets per minute (limits.max_packets_per_minute)", imei, settings.max_packets_per_minute));
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn sweep(&self, settings: &LimitSettings) {
+        let now = Instant::now();
+        let failure_window = Duration::from_secs(settings.handshake_failure_window_secs);
+        let mut ips = self.ips().lock().unwrap();
+        ips.retain(|_, state| !state.idle(now, failure_window));
+        metrics::gauge!("banned_ips").set(ips.values().filter(|s| s.banned(now)).count() as f64);
+        drop(ips);
+        // Untouched for a minute means the bucket is full again
+        self.packets().lock().unwrap().retain(|_, bucket| now.duration_since(bucket.updated) < Duration::from_secs(60));
+    }
+
+    pub fn spawn_sweeper(&'static self, shutdown: CancellationToken) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => self.sweep(&get_settings().limits),
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        });
+    }
+}
+
+impl LimitSettings {
+    fn is_exempt(&self, ip: IpAddr) -> bool {
+        self.exempt_ips.iter().any(|exempt| exempt.parse::<IpAddr>().is_ok_and(|e| e == ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_settings() -> LimitSettings {
+        LimitSettings {
+            max_connections_per_ip: 2,
+            max_packets_per_minute: 3,
+            handshake_failures_before_ban: 2,
+            handshake_failure_window_secs: 60,
+            ban_secs: 600,
+            exempt_ips: vec!["10.0.0.1".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ip_limits_and_bans() {
+        static TEST_LIMITS: Limits = Limits::new();
+        let settings = test_settings();
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+
+        let first = TEST_LIMITS.admit(ip, &settings).unwrap();
+        let _second = TEST_LIMITS.admit(ip, &settings).unwrap();
+        assert_eq!(TEST_LIMITS.admit(ip, &settings).err(), Some(Refusal::TooManyConnections));
+        drop(first);
+        let _third = TEST_LIMITS.admit(ip, &settings).unwrap();
+
+        TEST_LIMITS.handshake_failed(ip, &settings);
+        TEST_LIMITS.handshake_failed(ip, &settings);
+        assert_eq!(TEST_LIMITS.admit(ip, &settings).err(), Some(Refusal::Banned));
+
+        // Exempt addresses (e.g. a carrier NAT) are never capped or banned
+        let nat: IpAddr = "10.0.0.1".parse().unwrap();
+        let _slots: Vec<IpSlot> = (0..5).map(|_| TEST_LIMITS.admit(nat, &settings).unwrap()).collect();
+        TEST_LIMITS.handshake_failed(nat, &settings);
+        TEST_LIMITS.handshake_failed(nat, &settings);
+        assert!(TEST_LIMITS.admit(nat, &settings).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_packet_rate() {
+        let limits = Limits::new();
+        let settings = test_settings();
+        assert!((0..3).all(|_| limits.allow_packet("356307042441013", &settings)));
+        assert!(!limits.allow_packet("356307042441013", &settings));
+        assert!(limits.allow_packet("356307042441021", &settings));
+    }
+}
@@ -0,0 +1,100 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::{info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use crate::config::{OtlpSettings, Settings};
+
+/// Swaps the active `EnvFilter` on config reload.
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+pub struct Logging {
+    pub filter: FilterHandle,
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Logging {
+    /// Export the spans still buffered (end of `main`).
+    pub fn shutdown(&self) {
+        if let Some(provider) = &self.tracer_provider {
+            if let Err(e) = provider.shutdown() {
+                warn!("Failed to flush OpenTelemetry spans: {}", e);
+            }
+        }
+    }
+}
+
+/// `log.filter` when set, else `RUST_LOG`, else "info".
+fn build_filter(settings: &Settings) -> EnvFilter {
+    if !settings.log.filter.is_empty() {
+        if let Ok(filter) = EnvFilter::try_new(&settings.log.filter) {
+            return filter;
+        }
+    }
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+}
+
+fn build_tracer_provider(otlp: &OtlpSettings) -> Result<SdkTracerProvider, String> {
+    let mut exporter = SpanExporter::builder().with_http();
+    if !otlp.endpoint.is_empty() {
+        exporter = exporter.with_endpoint(otlp.endpoint.clone());
+    }
+    let exporter = exporter.build().map_err(|e| e.to_string())?;
+
+    let service_name = match otlp.service_name.as_str() {
+        "" => env!("CARGO_PKG_NAME").to_string(),
+        name => name.to_string(),
+    };
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(otlp.sample_ratio))))
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build())
+}
+
+/// Install the global subscriber: text or JSON lines (`log.format`), plus
+/// OpenTelemetry spans over OTLP when `log.otlp.enabled`.
+pub fn init(settings: &Settings) -> Logging {
+    let (filter, handle) = reload::Layer::new(build_filter(settings));
+    let json = settings.log.format == "json";
+
+    let (tracer_provider, otlp_error) = if settings.log.otlp.enabled {
+        match build_tracer_provider(&settings.log.otlp) {
+            Ok(provider) => (Some(provider), None),
+            Err(e) => (None, Some(e)),
+        }
+    } else {
+        (None, None)
+    };
+    let otel = tracer_provider.as_ref()
+        .map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer(env!("CARGO_PKG_NAME"))));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(json.then(|| fmt::layer().json()))
+        .with((!json).then(fmt::layer))
+        .with(otel)
+        .init();
+
+    match (&tracer_provider, otlp_error) {
+        (Some(_), _) => info!("Exporting traces over OTLP to {}", match settings.log.otlp.endpoint.as_str() {
+            "" => "the OTEL_EXPORTER_OTLP_ENDPOINT / default collector",
+            endpoint => endpoint,
+        }),
+        (None, Some(e)) => warn!("OTLP trace export disabled: {}", e),
+        (None, None) => {}
+    }
+
+    Logging { filter: handle, tracer_provider }
+}
+
+pub fn apply(handle: &FilterHandle, settings: &Settings) {
+    if let Err(e) = handle.reload(build_filter(settings)) {
+        warn!("Failed to update log filter: {}", e);
+    }
+}
@@ -1,62 +1,108 @@
 mod parser;
 mod db;
+mod devices;
+mod geofence;
 mod notifications;
 mod utils;
 mod webhook;
 mod monitor;
+mod presence;
+mod sink;
+mod cli;
+mod limits;
+mod logging;
+mod reload;
+mod session;
+mod shutdown;
+mod status;
+mod tls;
+mod tunnel;
 pub mod config;
 
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::time::{timeout, Duration};
 use tokio::sync::Semaphore;
-use parser::{TeltonikaParser};
-use db::{init_db, TeltonikaDataRepo};
-use notifications::TeamsNotificationService;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use parser::{codec_name, ParseError, TeltonikaParser};
+use devices::{Admission, DeviceRegistry};
+use geofence::GEOFENCES;
+use limits::LIMITS;
+use presence::PRESENCE;
+use session::{Session, SessionState, SESSIONS};
+use tls::TlsCertificates;
+use sink::TelemetrySink;
+use notifications::{NotificationService, Severity};
 use utils::format_record;
-use webhook::send_webhook_to_nauticoncept_api;
+use webhook::{jobs_for_batch, WebhookQueue};
 use bytes::Bytes;
 use std::env;
 use std::sync::Arc;
-use sqlx::PgPool;
-use tracing::{info, error, debug};
+use tracing::{info, info_span, error, debug, warn, Instrument};
 use rlimit::{setrlimit, getrlimit, Resource};
 use config::get_settings;
+use status::{ListenerState, SERVER};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load env
     dotenvy::from_path(".env").ok();
 
+    // Subcommands (e.g. `config check`) run instead of the server
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
     // Initialize config (will panic if fails, which is fine for startup)
     let settings = get_settings();
 
     // Initialize tracing with JSON support if requested
-    if env::var("RUST_LOG_FORMAT").unwrap_or_default() == "json" {
-        tracing_subscriber::fmt()
-            .json()
-            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-            .init();
-    } else {
-        tracing_subscriber::fmt::init();
+    let logging = logging::init(&settings);
+
+    let problems = settings.validate();
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("Invalid configuration: {}", problem);
+        }
+        return Err(format!("{} configuration problem(s), run `nc-teltonika-server config check`", problems.len()).into());
     }
     
     let port = settings.server.port;
-    
-    // JS: 1 * 60 * 1000 = 60000 ms = 1 minute.
-    let inactive_timeout_ms = 60000; 
-    
-    let (pool, _tunnel) = init_db().await?;
-    let pool = Arc::new(pool);
+    let storage = sink::from_settings(&settings).await?;
+    let tunnel = storage.tunnel;
+    let sink = storage.telemetry;
+    let devices = DeviceRegistry::new(storage.devices);
+
+    // Cancelled on SIGTERM/SIGINT: stops the accept loop and idle connections
+    let shutdown = CancellationToken::new();
+    // Cancelled once the last connection is gone, so the jobs it queued are still delivered
+    let webhook_shutdown = CancellationToken::new();
+
+    let webhooks = WebhookQueue::new(storage.webhooks);
+    let webhook_worker = webhooks.spawn_worker(webhook_shutdown.clone());
     
     // Start Monitor Server (Health + Metrics)
     let monitor_port = settings.server.monitor_port;
-    let monitor_pool = (*pool).clone();
+    let monitor_sink = sink.clone();
+    let monitor_webhooks = webhooks.clone();
+    let tunnel_status = tunnel.as_ref().map(|t| t.status());
     tokio::spawn(async move {
-        monitor::start(monitor_port, monitor_pool).await;
+        monitor::start(monitor_port, monitor_sink, monitor_webhooks, tunnel_status).await;
     });
 
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    let tls = if settings.server.tls.enabled {
+        let certificates = TlsCertificates::load(&settings.server.tls)?;
+        certificates.spawn_watcher(shutdown.clone());
+        let tls_listener = TcpListener::bind(format!("0.0.0.0:{}", settings.server.tls.port)).await?;
+        info!("TLS listener started on port {}", settings.server.tls.port);
+        Some((tls_listener, certificates))
+    } else {
+        None
+    };
+    SERVER.set_listener(ListenerState::Listening);
     info!("Server started on port {}", port);
 
     // Set file descriptor limit
@@ -77,124 +123,631 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     
     let version = env!("CARGO_PKG_VERSION");
-    TeamsNotificationService::note(&format!("nc-teltonika-server v{} started", version), "").await;
+    NotificationService::note(&format!("nc-teltonika-server v{} started", version), "");
     
     // Connection Limit
-    let max_connections = 5000;
+    let max_connections = settings.server.max_connections;
     let connection_semaphore = Arc::new(Semaphore::new(max_connections));
 
+    // Config reload (SIGHUP or POST /admin/reload); ports and the DB pool keep their startup values
+    reload::spawn_appliers(logging.filter.clone(), connection_semaphore.clone(), &settings);
+    reload::spawn_signal_handler();
+
+    let signal_token = shutdown.clone();
+    tokio::spawn(async move {
+        let name = shutdown::signal().await;
+        info!("Received {}, shutting down...", name);
+        // Readiness fails from here on
+        SERVER.set_listener(ListenerState::Draining);
+        signal_token.cancel();
+    });
+
+    LIMITS.spawn_sweeper(shutdown.clone());
+    PRESENCE.spawn_watcher(sink.clone(), webhooks.clone(), shutdown.clone());
+    GEOFENCES.spawn_seeder(sink.clone(), shutdown.clone());
+    let connections = TaskTracker::new();
+
     loop {
         // Wait for a permit if we are at limit (backpressure)
-        let permit = match connection_semaphore.clone().acquire_owned().await {
-            Ok(p) => p,
-            Err(e) => {
-                error!("Semaphore acquire error: {}", e);
-                break;
-            }
+        let permit = tokio::select! {
+            res = connection_semaphore.clone().acquire_owned() => match res {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("Semaphore acquire error: {}", e);
+                    break;
+                }
+            },
+            _ = shutdown.cancelled() => break,
         };
 
-        tokio::select! {
-            res = listener.accept() => {
-                match res {
-                    Ok((socket, addr)) => {
-                        debug!("client connected: {:?}", addr);
-                        metrics::gauge!("tcp_connections_active").increment(1.0);
-                        
-                        let pool = pool.clone();
-                        tokio::spawn(async move {
-                            // Hold permit until task finishes
-                            let _permit = permit;
-                            handle_client(socket, addr, pool, inactive_timeout_ms).await;
-                            metrics::gauge!("tcp_connections_active").decrement(1.0);
-                        });
+        let (res, acceptor) = tokio::select! {
+            res = listener.accept() => (res, None),
+            res = accept_tls(tls.as_ref().map(|(l, _)| l)) => (res, tls.as_ref().map(|(_, c)| c.acceptor())),
+            _ = shutdown.cancelled() => break,
+        };
+
+        match res {
+            Ok((socket, addr)) => {
+                debug!("client connected: {:?} (tls: {})", addr, acceptor.is_some());
+                let ip_slot = match LIMITS.admit(addr.ip(), &get_settings().limits) {
+                    Ok(slot) => slot,
+                    Err(refusal) => {
+                        debug!("Refusing {}: {:?}", addr, refusal);
+                        drop(socket);
+                        continue;
                     }
-                    Err(e) => {
-                         error!("Accept error: {}", e);
-                         // Don't hold permit if accept failed
-                        drop(permit);
+                };
+                SERVER.connection_opened();
+                let opened_at = std::time::Instant::now();
+                
+                let sink = sink.clone();
+                let webhooks = webhooks.clone();
+                let devices = devices.clone();
+                let inactive_timeout_ms = get_settings().server.inactivity_timeout_ms;
+                let shutdown = shutdown.clone();
+                // IMEI is filled in by handle_client after the handshake
+                let span = info_span!("connection", peer = %addr, tls = acceptor.is_some(), imei = tracing::field::Empty);
+                connections.spawn(async move {
+                    // Hold permit and per-IP slot until task finishes
+                    let _permit = permit;
+                    let _ip_slot = ip_slot;
+                    match acceptor {
+                        None => handle_client(socket, addr, sink, webhooks, devices, inactive_timeout_ms, shutdown).await,
+                        // Handshake in the connection task so a slow client never blocks the accept loop
+                        Some(acceptor) => match timeout(Duration::from_millis(inactive_timeout_ms), acceptor.accept(socket)).await {
+                            Ok(Ok(stream)) => handle_client(stream, addr, sink, webhooks, devices, inactive_timeout_ms, shutdown).await,
+                            Ok(Err(e)) => {
+                                debug!("TLS handshake with {} failed: {}", addr, e);
+                                metrics::counter!("tls_handshake_failures_total").increment(1);
+                                LIMITS.handshake_failed(addr.ip(), &get_settings().limits);
+                            }
+                            Err(_) => {
+                                debug!("TLS handshake with {} timed out", addr);
+                                metrics::counter!("tls_handshake_failures_total").increment(1);
+                                LIMITS.handshake_failed(addr.ip(), &get_settings().limits);
+                            }
+                        },
                     }
-                }
+                    SERVER.connection_closed(opened_at);
+                }.instrument(span));
             }
-            _ = tokio::signal::ctrl_c() => {
-                info!("Received Ctrl+C, shutting down...");
-                break;
+            Err(e) => {
+                 error!("Accept error: {}", e);
+                 // Don't hold permit if accept failed
+                drop(permit);
             }
         }
     }
 
+    // Graceful shutdown: no new devices, let current packets finish and be ACKed,
+    // then flush webhooks and the database, all within server.shutdown_timeout_ms
+    drop(listener);
+    drop(tls);
+    connections.close();
+    let draining = connections.len();
+    let deadline = Duration::from_millis(get_settings().server.shutdown_timeout_ms);
+    info!("Stopped accepting connections, draining {} connection(s) (deadline {:?})", draining, deadline);
+
+    let drained = timeout(deadline, async {
+        connections.wait().await;
+        webhook_shutdown.cancel();
+        if let Err(e) = webhook_worker.await {
+            error!("Webhook worker failed: {}", e);
+        }
+        sink.close().await;
+    }).await;
+
+    let summary = match drained {
+        Ok(()) => format!("Drained {} connection(s), delivered the due webhooks and closed the database.", draining),
+        Err(_) => format!("Shutdown deadline of {:?} exceeded with {} connection(s) still open; pending webhooks stay queued.", deadline, connections.len()),
+    };
+    match drained {
+        Ok(()) => info!("{}", summary),
+        Err(_) => error!("{}", summary),
+    }
+
+    SERVER.set_listener(ListenerState::Stopped);
+    if let Some(tunnel) = tunnel {
+        tunnel.shutdown().await;
+    }
+
+    // Bounded as well: a hung alert channel must not block the exit
+    let title = format!("nc-teltonika-server v{} stopped", version);
+    let _ = timeout(Duration::from_secs(10), NotificationService::notify_now(Severity::Info, &title, &summary)).await;
+
+    // Blocking export of the last span batch, off the runtime threads
+    let _ = tokio::task::spawn_blocking(move || logging.shutdown()).await;
+
     Ok(())
 }
 
-async fn handle_client(mut socket: TcpStream, addr: std::net::SocketAddr, pool: Arc<PgPool>, timeout_ms: u64) {
-    let mut imei = String::new();
+/// `accept()` on the TLS listener; never resolves when TLS is disabled.
+async fn accept_tls(listener: Option<&TcpListener>) -> std::io::Result<(TcpStream, std::net::SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn handle_client<S>(mut socket: S, addr: std::net::SocketAddr, sink: Arc<dyn TelemetrySink>, webhooks: Arc<WebhookQueue>, devices: Arc<DeviceRegistry>, timeout_ms: u64, shutdown: CancellationToken)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut session = Session::new(addr);
+    // Cancelled when a newer connection for the same IMEI takes over
+    let replaced = CancellationToken::new();
     let mut buf = [0u8; 8192];
-    let timeout_duration = Duration::from_millis(timeout_ms);
+    // Replaced by the device's group override once the IMEI is known
+    let mut timeout_duration = Duration::from_millis(timeout_ms);
+    // Insert of the `device_sessions` row, started once the handshake is accepted
+    let mut session_row: Option<tokio::task::JoinHandle<Option<i64>>> = None;
 
-    loop {
-        let read_res = timeout(timeout_duration, socket.read(&mut buf)).await;
+    let reason = loop {
+        // Shutdown only interrupts the wait for the next packet, never one being processed
+        let read_res = tokio::select! {
+            res = timeout(timeout_duration, socket.read(&mut buf)) => res,
+            _ = shutdown.cancelled() => break "shutdown",
+            _ = replaced.cancelled() => break "replaced",
+        };
+        // TLS peers often drop the socket without close_notify: same as a plain EOF
+        let read_res = match read_res {
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(Ok(0)),
+            other => other,
+        };
+        let awaiting_imei = session.state() == SessionState::AwaitingImei;
         
         match read_res {
-             Ok(Ok(n)) if n == 0 => {
+             Ok(Ok(0)) => {
                  debug!("client disconnected");
-                 return;
+                 if awaiting_imei {
+                     metrics::counter!("handshake_failures_total", "reason" => "closed").increment(1);
+                 }
+                 break "eof";
              },
              Ok(Ok(n)) => {
+                 let received_at = chrono::Utc::now();
+                 let started = std::time::Instant::now();
                  metrics::counter!("packets_received_total").increment(1);
+                 metrics::counter!("bytes_received_total").increment(n as u64);
+                 session.received(n);
+                 SERVER.packet_received(received_at);
                  debug!("Received data from {}, length: {} bytes", addr, n);
                  debug!("{}", hex::encode(&buf[0..n]));
                  
                  let data = Bytes::copy_from_slice(&buf[0..n]);
                  
                  // Create parser
-                 let parser = TeltonikaParser::new(data.clone());
+                 let allow_imeisv = get_settings().devices.allow_imeisv;
+                 let parser = info_span!("parse", bytes = n).in_scope(|| TeltonikaParser::parse(data.clone(), allow_imeisv));
                  
                  if parser.invalid {
                      debug!("❌ Invalid data received, closing connection");
-                     return;
+                     if let Some(error) = parser.error {
+                         metrics::counter!("parse_errors_total", "kind" => error.kind()).increment(1);
+                         match error {
+                             ParseError::UnsupportedCodec(codec_id) => {
+                                 metrics::counter!("packets_by_codec_total", "codec" => codec_name(codec_id)).increment(1);
+                             }
+                             ParseError::InvalidImei(reason) => {
+                                 warn!("❌ Invalid IMEI {:?} from {} ({}), rejecting", String::from_utf8_lossy(&data[2..]), addr, reason);
+                                 // Only a device waiting for its handshake reply expects the reject byte
+                                 if awaiting_imei && socket.write_all(&[0]).await.is_ok() {
+                                     metrics::counter!("bytes_sent_total").increment(1);
+                                     session.sent(1);
+                                 }
+                             }
+                             _ => {}
+                         }
+                     }
+                     if awaiting_imei {
+                         metrics::counter!("handshake_failures_total", "reason" => "invalid").increment(1);
+                     }
+                     break "parse_error";
                  }
                  
                  if parser.is_imei {
                      if let Some(i) = parser.imei {
-                         imei = i;
+                         if let Err(e) = session.expect_imei() {
+                             warn!("❌ {} sent a second IMEI ({}), closing", addr, i);
+                             metrics::counter!("protocol_errors_total", "kind" => e.kind()).increment(1);
+                             break "protocol_error";
+                         }
+                         if let Admission::Rejected(reason) = devices.authorize(&get_settings().devices, &i).await {
+                             warn!("❌ Rejected IMEI {} from {} ({})", i, addr, reason);
+                             metrics::counter!("handshake_failures_total", "reason" => "rejected").increment(1);
+                             if socket.write_all(&[0]).await.is_ok() {
+                                 metrics::counter!("bytes_sent_total").increment(1);
+                                 session.sent(1);
+                             }
+                             break "rejected";
+                         }
+                         if !SESSIONS.register(&i, &session, replaced.clone(), get_settings().devices.duplicate_sessions) {
+                             if socket.write_all(&[0]).await.is_ok() {
+                                 metrics::counter!("bytes_sent_total").increment(1);
+                                 session.sent(1);
+                             }
+                             break "duplicate";
+                         }
+                         tracing::Span::current().record("imei", i.as_str());
+                         timeout_duration = Duration::from_millis(get_settings().inactivity_timeout_ms_for(&i));
+                         SERVER.device_seen(&i, received_at);
+                         PRESENCE.seen(&i, received_at);
+                         session.authenticate(i);
+                         // Off the ACK path: with the database down the insert waits for database.acquire_timeout_ms
+                         let (row_sink, imei, connected_at) = (sink.clone(), session.imei().to_string(), session.connected_at());
+                         session_row = Some(tokio::spawn(async move {
+                             row_sink.open_session(&imei, &addr.to_string(), connected_at).await
+                                 .unwrap_or_else(|e| {
+                                     NotificationService::sql_error(e.sql(), &format!("{:?}", e));
+                                     None
+                                 })
+                         }));
                          // Send ACK (0x01)
-                         if let Err(_) = socket.write_all(&[1]).await {
-                             return;
+                         if socket.write_all(&[1]).await.is_err() {
+                             break "write_error";
                          }
+                         metrics::counter!("bytes_sent_total").increment(1);
+                         session.sent(1);
                      }
                  } else if let Some(avl) = parser.avl_data {
+                     metrics::counter!("packets_by_codec_total", "codec" => codec_name(avl.codec_id)).increment(1);
+                     if let Err(e) = session.expect_data() {
+                         warn!("❌ {} sent AVL data before its IMEI, closing", addr);
+                         metrics::counter!("protocol_errors_total", "kind" => e.kind()).increment(1);
+                         metrics::counter!("handshake_failures_total", "reason" => "no_imei").increment(1);
+                         break "protocol_error";
+                     }
+                     if !LIMITS.allow_packet(session.imei(), &get_settings().limits) {
+                         warn!("❌ {} exceeds limits.max_packets_per_minute, closing without ACK", session.imei());
+                         break "rate_limited";
+                     }
+                     // Only a decoded packet on an authenticated session counts as the device being online
+                     SERVER.device_seen(session.imei(), received_at);
+                     PRESENCE.seen(session.imei(), received_at);
                      if avl.records.is_empty() {
-                         return; // Close if no records? JS: `if (!avl || !avl.number_of_data) c.end()`
+                         break "empty_packet"; // JS: `if (!avl || !avl.number_of_data) c.end()`
                      }
+                     metrics::counter!("records_decoded_total").increment(avl.records.len() as u64);
                      
                      for record in &avl.records {
                          debug!("{}", format_record(record));
                      }
+
+                     let imei = session.imei();
                      
                      // DB Save
                      let start = std::time::Instant::now();
-                     TeltonikaDataRepo::save_avl_data(&pool, &imei, &avl.records, &hex::encode(&data), "new").await;
+                     let row_id = match sink.save_batch(imei, &avl.records, &hex::encode(&data), "new")
+                         .instrument(info_span!("db.save", records = avl.records.len()))
+                         .await
+                     {
+                         Ok(id) => id,
+                         Err(e) => {
+                             // No ACK: the device keeps the records and resends them later
+                             warn!("❌ Could not store {} record(s) from {}, closing without ACK", avl.records.len(), imei);
+                             NotificationService::sql_error(e.sql(), &format!("{:?}", e));
+                             break "storage_error";
+                         }
+                     };
                      metrics::histogram!("db_query_duration_seconds").record(start.elapsed().as_secs_f64());
+
+                     if let Some(latest) = avl.records.iter().max_by_key(|r| r.timestamp) {
+                         if let Err(e) = sink.upsert_state(imei, latest).instrument(info_span!("db.upsert_state")).await {
+                             NotificationService::sql_error(e.sql(), &format!("{:?}", e));
+                         }
+                     }
                      
-                     // Webhook
-                     send_webhook_to_nauticoncept_api().await;
+                     let settings = get_settings();
+                     let geofences = GEOFENCES.check(&settings, imei, &avl.records);
+                     for state in &geofences.states {
+                         if let Err(e) = sink.upsert_geofence_state(state).instrument(info_span!("db.upsert_geofence_state")).await {
+                             NotificationService::sql_error(e.sql(), &format!("{:?}", e));
+                         }
+                     }
+
+                     // Webhooks (delivered in the background, see webhook::queue)
+                     async {
+                         for job in jobs_for_batch(&settings, imei, received_at, row_id, &avl.records) {
+                             webhooks.enqueue(job).await;
+                         }
+                         for crossing in &geofences.crossings {
+                             for job in geofence::announce(&settings, imei, received_at, crossing) {
+                                 webhooks.enqueue(job).await;
+                             }
+                         }
+                     }.instrument(info_span!("webhook.enqueue")).await;
                      
                      // Send ACK: 4 bytes (Number of Data as Big Endian int32)
                      let count = avl.number_of_data as u32;
                      let ack = count.to_be_bytes();
-                     if let Err(_) = socket.write_all(&ack).await {
-                          return;
+                     if socket.write_all(&ack).await.is_err() {
+                          break "write_error";
                      }
+                     metrics::counter!("bytes_sent_total").increment(ack.len() as u64);
+                     session.sent(ack.len());
+                     session.packet(avl.records.len());
+                     metrics::histogram!("ack_latency_seconds").record(started.elapsed().as_secs_f64());
                      info!("✅ Sent ACK: {} record(s) to {}", count, addr);
                  }
              },
              Err(_) => {
                  debug!("Client timed out due to inactivity");
-                 return;
+                 if awaiting_imei {
+                     metrics::counter!("handshake_failures_total", "reason" => "timeout").increment(1);
+                 }
+                 break "timeout";
              },
              Ok(Err(e)) => {
                  error!("Error reading from socket: {}", e);
-                 return;
+                 break "read_error";
              }
         }
+    };
+
+    // A device refused as duplicate is legitimate and retries: not a failure towards a ban
+    if session.state() == SessionState::AwaitingImei && !matches!(reason, "shutdown" | "duplicate") {
+        LIMITS.handshake_failed(addr.ip(), &get_settings().limits);
+    }
+    SESSIONS.unregister(&session);
+    let row_id = match session_row {
+        Some(opening) => opening.await.ok().flatten(),
+        None => None,
+    };
+    if let Some(id) = row_id {
+        if let Err(e) = sink.close_session(id, &session.summary(reason)).await {
+            NotificationService::sql_error(e.sql(), &format!("{:?}", e));
+        }
+    }
+    session.close(reason);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sink::MemorySink;
+    use tokio::io::DuplexStream;
+    use tokio::task::JoinHandle;
+
+    // Same packet as parser::codec8e::tests::test_bot_payload_match, with preamble, header and CRC.
+    const AVL_PACKET_HEX: &str = "00000000000000978e010000019c6d352b580000000000000000000000000000000000000018000c00010000150500450000711e00b30000c80300ed0200ef0000f000017f0033d20333d30a0008001100100012ffe00013ffe900430e03004600c700b5000000b6000001820000000300090000003b01c100015040032000000000000000010281001438393838333033303030303038363639393833390100001e6c";
+
+    /// IMEI login packet. Every test uses its own IMEI: the session registry is
+    /// global, and a shared IMEI would let one test's connection replace another's.
+    fn handshake(imei: &str) -> Vec<u8> {
+        let mut packet = (imei.len() as u16).to_be_bytes().to_vec();
+        packet.extend_from_slice(imei.as_bytes());
+        packet
+    }
+
+    /// `handle_client` task for a device at 127.0.0.1:5000; returns the device end of the pipe.
+    fn spawn_client(sink: &Arc<MemorySink>, timeout_ms: u64, shutdown: CancellationToken) -> (DuplexStream, JoinHandle<()>) {
+        let (device, server) = tokio::io::duplex(8192);
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        let task = tokio::spawn(handle_client(server, addr, sink.clone(), WebhookQueue::new(sink.clone()), DeviceRegistry::new(sink.clone()), timeout_ms, shutdown));
+        (device, task)
+    }
+
+    #[tokio::test]
+    async fn test_handle_client_with_memory_sink() {
+        let sink = Arc::new(MemorySink::new());
+        let (mut device, task) = spawn_client(&sink, 1000, CancellationToken::new());
+
+        let handshake = handshake("356307042441013");
+        device.write_all(&handshake).await.unwrap();
+
+        let mut ack = [0u8; 1];
+        device.read_exact(&mut ack).await.unwrap();
+        assert_eq!(ack, [1]);
+
+        device.write_all(&hex::decode(AVL_PACKET_HEX).unwrap()).await.unwrap();
+        let mut ack = [0u8; 4];
+        device.read_exact(&mut ack).await.unwrap();
+        assert_eq!(u32::from_be_bytes(ack), 1);
+
+        drop(device);
+        task.await.unwrap();
+
+        let batches = sink.batches();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].imei, "356307042441013");
+        assert_eq!(batches[0].records.len(), 1);
+        assert_eq!(batches[0].raw, AVL_PACKET_HEX);
+        assert_eq!(batches[0].status, "new");
+        assert!(sink.state("356307042441013").is_some());
+
+        let sessions = sink.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!((sessions[0].imei.as_str(), sessions[0].peer.as_str()), ("356307042441013", "127.0.0.1:5000"));
+        let summary = sessions[0].summary.as_ref().unwrap();
+        assert!(sessions[0].connected_at <= summary.disconnected_at);
+        assert_eq!(summary.reason, "eof");
+        assert_eq!((summary.packets, summary.records), (1, 1));
+        assert_eq!(summary.bytes_received, (handshake.len() + AVL_PACKET_HEX.len() / 2) as i64);
+        assert_eq!(summary.bytes_sent, 5);
+    }
+
+    #[tokio::test]
+    async fn test_handle_client_closes_on_shutdown() {
+        let sink = Arc::new(MemorySink::new());
+        let shutdown = CancellationToken::new();
+        let (mut device, task) = spawn_client(&sink, 60_000, shutdown.clone());
+
+        let handshake = handshake("356307042441021");
+        device.write_all(&handshake).await.unwrap();
+        let mut ack = [0u8; 1];
+        device.read_exact(&mut ack).await.unwrap();
+
+        device.write_all(&hex::decode(AVL_PACKET_HEX).unwrap()).await.unwrap();
+        let mut ack = [0u8; 4];
+        device.read_exact(&mut ack).await.unwrap();
+
+        // Idle connection: closed right away instead of waiting for the inactivity timeout
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+        assert_eq!(sink.batches().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handle_client_no_ack_when_storage_fails() {
+        let sink = Arc::new(MemorySink::new());
+        let (mut device, task) = spawn_client(&sink, 1000, CancellationToken::new());
+
+        let handshake = handshake("356307042441039");
+        device.write_all(&handshake).await.unwrap();
+        let mut ack = [0u8; 1];
+        device.read_exact(&mut ack).await.unwrap();
+
+        // Database goes down: the packet must not be ACKed, so the device keeps it
+        sink.set_unavailable(true);
+        device.write_all(&hex::decode(AVL_PACKET_HEX).unwrap()).await.unwrap();
+        let mut rest = Vec::new();
+        device.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        task.await.unwrap();
+        assert!(sink.batches().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_client_acks_when_session_row_fails() {
+        let sink = Arc::new(MemorySink::new());
+        sink.set_unavailable(true);
+        let (mut device, task) = spawn_client(&sink, 1000, CancellationToken::new());
+
+        let handshake = handshake("356307042441047");
+        device.write_all(&handshake).await.unwrap();
+        let mut ack = [0u8; 1];
+        device.read_exact(&mut ack).await.unwrap();
+        assert_eq!(ack, [1]);
+
+        drop(device);
+        task.await.unwrap();
+        assert!(sink.sessions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_client_enforces_handshake() {
+        let sink = Arc::new(MemorySink::new());
+        let handshake = handshake("356307042441054");
+
+        // AVL data before the IMEI: closed without ACK or storage
+        let (mut device, task) = spawn_client(&sink, 1000, CancellationToken::new());
+        device.write_all(&hex::decode(AVL_PACKET_HEX).unwrap()).await.unwrap();
+        task.await.unwrap();
+        let mut rest = Vec::new();
+        device.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        assert!(sink.batches().is_empty());
+
+        // A second IMEI is a protocol error
+        let (mut device, task) = spawn_client(&sink, 1000, CancellationToken::new());
+        device.write_all(&handshake).await.unwrap();
+        let mut ack = [0u8; 1];
+        device.read_exact(&mut ack).await.unwrap();
+        assert_eq!(ack, [1]);
+        device.write_all(&handshake).await.unwrap();
+        task.await.unwrap();
+        let mut rest = Vec::new();
+        device.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_client_rejects_invalid_imei() {
+        let sink = Arc::new(MemorySink::new());
+        // Wrong Luhn check digit
+        let invalid = handshake("356307042441071");
+
+        // Still awaiting the handshake: the device gets the 0x00 reject
+        let (mut device, task) = spawn_client(&sink, 1000, CancellationToken::new());
+        device.write_all(&invalid).await.unwrap();
+        task.await.unwrap();
+        let mut rest = Vec::new();
+        device.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, [0]);
+
+        // Already authenticated: no handshake reply is expected, just the close
+        let (mut device, task) = spawn_client(&sink, 1000, CancellationToken::new());
+        device.write_all(&handshake("356307042441070")).await.unwrap();
+        let mut ack = [0u8; 1];
+        device.read_exact(&mut ack).await.unwrap();
+        assert_eq!(ack, [1]);
+        device.write_all(&invalid).await.unwrap();
+        task.await.unwrap();
+        let mut rest = Vec::new();
+        device.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_client_over_tls() {
+        use tokio_rustls::rustls::{self, pki_types::ServerName, ClientConfig, RootCertStore};
+
+        let dir = std::env::temp_dir().join(format!("nc-teltonika-tls-client-{}", std::process::id()));
+        let settings = tls::tests::self_signed(&dir);
+        let certificates = TlsCertificates::load(&settings).unwrap();
+
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut std::io::BufReader::new(std::fs::File::open(&settings.cert_path).unwrap())) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let client = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions().unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let sink = Arc::new(MemorySink::new());
+        let (device, server) = tokio::io::duplex(8192);
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        let acceptor = certificates.acceptor();
+        let server_sink = sink.clone();
+        let task = tokio::spawn(async move {
+            let stream = acceptor.accept(server).await.unwrap();
+            handle_client(stream, addr, server_sink.clone(), WebhookQueue::new(server_sink.clone()), DeviceRegistry::new(server_sink), 1000, CancellationToken::new()).await;
+        });
+
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+        let mut device = connector.connect(ServerName::try_from("localhost").unwrap(), device).await.unwrap();
+
+        let handshake = handshake("356307042441062");
+        device.write_all(&handshake).await.unwrap();
+        let mut ack = [0u8; 1];
+        device.read_exact(&mut ack).await.unwrap();
+        assert_eq!(ack, [1]);
+
+        device.write_all(&hex::decode(AVL_PACKET_HEX).unwrap()).await.unwrap();
+        let mut ack = [0u8; 4];
+        device.read_exact(&mut ack).await.unwrap();
+        assert_eq!(u32::from_be_bytes(ack), 1);
+
+        device.shutdown().await.unwrap();
+        task.await.unwrap();
+        assert_eq!(sink.batches().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handle_client_replaces_older_session() {
+        let sink = Arc::new(MemorySink::new());
+        let handshake = handshake("490154203237518");
+
+        let (mut old, old_task) = spawn_client(&sink, 60_000, CancellationToken::new());
+        old.write_all(&handshake).await.unwrap();
+        let mut ack = [0u8; 1];
+        old.read_exact(&mut ack).await.unwrap();
+
+        let (mut new, new_task) = spawn_client(&sink, 60_000, CancellationToken::new());
+        new.write_all(&handshake).await.unwrap();
+        new.read_exact(&mut ack).await.unwrap();
+        assert_eq!(ack, [1]);
+
+        // The lingering socket is closed right away, the new one keeps working
+        tokio::time::timeout(Duration::from_secs(1), old_task).await.unwrap().unwrap();
+        new.write_all(&hex::decode(AVL_PACKET_HEX).unwrap()).await.unwrap();
+        let mut ack = [0u8; 4];
+        new.read_exact(&mut ack).await.unwrap();
+        assert_eq!(u32::from_be_bytes(ack), 1);
+
+        drop(new);
+        new_task.await.unwrap();
     }
 }
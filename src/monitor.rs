@@ -1,24 +1,40 @@
 use axum::{
-    routing::get,
+    routing::{get, post},
     Router,
     response::{IntoResponse, Response},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
+    Json,
 };
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use std::net::SocketAddr;
 use metrics_exporter_prometheus::PrometheusBuilder;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::watch;
 use tracing::info;
-use sqlx::PgPool;
 
-use crate::db::TeltonikaDataRepo;
+use crate::config::get_settings;
+use crate::reload;
+use crate::session::SESSIONS;
+use crate::sink::TelemetrySink;
+use crate::status::{ListenerState, SERVER};
+use crate::tunnel::{TunnelState, TunnelStatus};
+use crate::webhook::WebhookQueue;
 
-pub async fn start(port: u16, db_pool: PgPool) {
+pub async fn start(port: u16, sink: Arc<dyn TelemetrySink>, webhooks: Arc<WebhookQueue>, tunnel: Option<watch::Receiver<TunnelStatus>>) {
     let builder = PrometheusBuilder::new();
     let recorder_handle = builder.install_recorder()
         .expect("failed to install Prometheus recorder");
 
     let app = Router::new()
-        .route("/health", get(move || health_handler(db_pool)))
-        .route("/metrics", get(move || std::future::ready(recorder_handle.render())));
+        .route("/livez", get(livez_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/health", get(move || health_handler(sink.clone(), webhooks.clone(), tunnel.clone())))
+        .route("/metrics", get(move || std::future::ready(recorder_handle.render())))
+        .route("/config", get(config_handler))
+        .route("/admin/reload", post(reload_handler));
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     info!("Helper HTTP server (health/metrics) listening on {}", addr);
@@ -27,12 +43,142 @@ pub async fn start(port: u16, db_pool: PgPool) {
     axum::serve(listener, app).await.unwrap();
 }
 
-async fn health_handler(pool: PgPool) -> Response {
-    match TeltonikaDataRepo::check_health(&pool).await {
-        Ok(_) => (StatusCode::OK, "OK").into_response(),
+/// Process is up and the runtime answers; never checks dependencies.
+async fn livez_handler() -> &'static str {
+    "OK"
+}
+
+/// Whether devices should be routed here: false until the listener is bound and
+/// as soon as shutdown starts draining. A database outage does not make the
+/// server unready, since devices are still accepted (see `/health`).
+async fn readyz_handler() -> Response {
+    match SERVER.listener() {
+        ListenerState::Listening => (StatusCode::OK, "READY").into_response(),
+        state => (StatusCode::SERVICE_UNAVAILABLE, format!("NOT READY: listener {:?}", state)).into_response(),
+    }
+}
+
+/// Component statuses as JSON; `503` unless everything is up.
+async fn health_handler(sink: Arc<dyn TelemetrySink>, webhooks: Arc<WebhookQueue>, tunnel: Option<watch::Receiver<TunnelStatus>>) -> Response {
+    let settings = get_settings();
+    let mut degraded = false;
+
+    let start = Instant::now();
+    let database = match sink.check_health().await {
+        Ok(()) => json!({ "status": "up", "latency_ms": start.elapsed().as_millis() as u64 }),
         Err(e) => {
             tracing::error!("Health check failed: {}", e);
-            (StatusCode::SERVICE_UNAVAILABLE, "DB connection failed").into_response()
+            degraded = true;
+            json!({ "status": "down", "error": e.to_string() })
+        }
+    };
+
+    let tunnel = match tunnel.map(|t| t.borrow().clone()) {
+        Some(status) => {
+            degraded |= status.state != TunnelState::Up;
+            json!({ "status": status.state, "since": status.since, "reconnects": status.reconnects, "last_error": status.last_error })
         }
+        None => json!({ "status": "disabled" }),
+    };
+
+    let webhook_queue = match webhooks.depth().await {
+        Ok(depth) => json!({ "status": "up", "depth": depth }),
+        Err(e) => {
+            degraded = true;
+            json!({ "status": "down", "error": e.to_string() })
+        }
+    };
+
+    let listener = SERVER.listener();
+    let status = match (listener, degraded) {
+        (ListenerState::Listening, false) => "ok",
+        (ListenerState::Listening, true) => "degraded",
+        (ListenerState::Starting, _) => "starting",
+        _ => "draining",
+    };
+
+    let body = json!({
+        "status": status,
+        "version": env!("CARGO_PKG_VERSION"),
+        "components": {
+            "database": database,
+            "ssh_tunnel": tunnel,
+            "webhook_queue": webhook_queue,
+            "listener": { "status": listener, "port": settings.server.port },
+            "connections": { "active": SERVER.active_connections(), "authenticated_devices": SESSIONS.len(), "limit": settings.server.max_connections },
+        },
+        "last_packet_at": SERVER.last_packet_at(),
+    });
+
+    let code = if status == "ok" { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(body)).into_response()
+}
+
+/// Effective runtime limits, to check what a deployment actually runs with.
+async fn config_handler() -> Json<serde_json::Value> {
+    let settings = get_settings();
+    Json(serde_json::json!({
+        "env": settings.env,
+        "port": settings.server.port,
+        "monitor_port": settings.server.monitor_port,
+        "inactivity_timeout_ms": settings.server.inactivity_timeout_ms,
+        "group_timeouts_ms": settings.server.group_timeouts_ms,
+        "max_connections": settings.server.max_connections,
+        "shutdown_timeout_ms": settings.server.shutdown_timeout_ms,
+        "db_pool_size": settings.database.pool_size,
+        "db_acquire_timeout_ms": settings.database.acquire_timeout_ms,
+        "storage_backend": settings.storage.backend,
+    }))
+}
+
+/// Reload the configuration, same as SIGHUP. Requires `server.admin_token`.
+async fn reload_handler(headers: HeaderMap) -> Response {
+    let Some(token) = get_settings().server.admin_token.clone().filter(|t| !t.is_empty()) else {
+        return (StatusCode::NOT_FOUND, "Admin endpoints are disabled").into_response();
+    };
+    let authorized = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|v| token_matches(v, &token));
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    }
+
+    match reload::reload() {
+        Ok(()) => Json(serde_json::json!({ "status": "reloaded" })).into_response(),
+        Err(problems) => (StatusCode::UNPROCESSABLE_ENTITY, Json(serde_json::json!({ "status": "rejected", "problems": problems }))).into_response(),
+    }
+}
+
+/// Compare HMAC digests of both tokens in constant time, so the response
+/// time leaks neither the token's length nor a matching prefix.
+fn token_matches(presented: &str, token: &str) -> bool {
+    let digest = |value: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"admin-token").expect("HMAC accepts any key length");
+        mac.update(value.as_bytes());
+        mac
+    };
+    digest(token).verify_slice(&digest(presented).finalize().into_bytes()).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_readyz_fails_while_draining() {
+        SERVER.set_listener(ListenerState::Listening);
+        assert_eq!(readyz_handler().await.status(), StatusCode::OK);
+
+        SERVER.set_listener(ListenerState::Draining);
+        assert_eq!(readyz_handler().await.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cre", "s3cret"));
+        assert!(!token_matches("s3cret!", "s3cret"));
+        assert!(!token_matches("", "s3cret"));
     }
 }
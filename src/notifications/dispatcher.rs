@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::warn;
+
+use super::{Notification, Severity};
+
+struct Burst {
+    window_start: Instant,
+    last_seen: Instant,
+    /// Occurrences swallowed since the last message actually sent.
+    suppressed: u64,
+    latest: Notification,
+}
+
+/// Collapses identical alerts (same severity and title) within a time window.
+///
+/// The first alert of a burst goes out immediately; repeats are only counted.
+/// When the window closes, one digest ("SQL ERROR ×3421 in last 5 min") is
+/// emitted if anything was swallowed, and the burst is forgotten once a full
+/// window passes without a repeat.
+pub struct Coalescer {
+    window: Duration,
+    bursts: HashMap<(Severity, String), Burst>,
+}
+
+fn format_window(window: Duration) -> String {
+    let secs = window.as_secs();
+    if secs >= 60 && secs.is_multiple_of(60) {
+        format!("{} min", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+impl Coalescer {
+    pub fn new(window: Duration) -> Self {
+        Coalescer { window, bursts: HashMap::new() }
+    }
+
+    /// Returns the notification if it should be sent right away.
+    pub fn offer(&mut self, notification: Notification, now: Instant) -> Option<Notification> {
+        let key = (notification.severity, notification.title.clone());
+        match self.bursts.get_mut(&key) {
+            Some(burst) => {
+                burst.suppressed += 1;
+                burst.last_seen = now;
+                burst.latest = notification;
+                None
+            }
+            None => {
+                self.bursts.insert(key, Burst {
+                    window_start: now,
+                    last_seen: now,
+                    suppressed: 0,
+                    latest: notification.clone(),
+                });
+                Some(notification)
+            }
+        }
+    }
+
+    /// Digests for every window that closed before `now`.
+    pub fn flush_due(&mut self, now: Instant) -> Vec<Notification> {
+        let window = self.window;
+        let mut digests = Vec::new();
+
+        self.bursts.retain(|_, burst| {
+            if now.duration_since(burst.window_start) < window {
+                return true;
+            }
+            if burst.suppressed > 0 {
+                digests.push(Notification {
+                    severity: burst.latest.severity,
+                    title: format!("{} ×{} in last {}", burst.latest.title, burst.suppressed, format_window(window)),
+                    message: format!("Latest occurrence:<br/>{}", burst.latest.message),
+                });
+                burst.suppressed = 0;
+                burst.window_start = now;
+                return true;
+            }
+            // Quiet for a whole window: the burst is over
+            now.duration_since(burst.last_seen) < window
+        });
+
+        digests
+    }
+}
+
+/// Receives alerts from `NotificationService` and sends them off the packet path.
+pub async fn run(mut rx: mpsc::Receiver<Notification>, window: Duration) {
+    let mut coalescer = Coalescer::new(window);
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                let Some(notification) = msg else { break };
+                if let Some(n) = coalescer.offer(notification, Instant::now()) {
+                    super::deliver(&n).await;
+                }
+            }
+            _ = tick.tick() => {
+                for digest in coalescer.flush_due(Instant::now()) {
+                    super::deliver(&digest).await;
+                }
+            }
+        }
+    }
+
+    // Channel closed: flush whatever is still being counted
+    for digest in coalescer.flush_due(Instant::now() + window) {
+        super::deliver(&digest).await;
+    }
+    warn!("Notification dispatcher stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(title: &str) -> Notification {
+        Notification { severity: Severity::Error, title: title.to_string(), message: "boom".to_string() }
+    }
+
+    #[test]
+    fn test_coalesces_identical_alerts() {
+        let window = Duration::from_secs(300);
+        let mut coalescer = Coalescer::new(window);
+        let t0 = Instant::now();
+
+        assert!(coalescer.offer(alert("SQL ERROR"), t0).is_some());
+        for i in 1..=3421 {
+            assert!(coalescer.offer(alert("SQL ERROR"), t0 + Duration::from_millis(i)).is_none());
+        }
+        // A different title is not coalesced with the first one
+        assert!(coalescer.offer(alert("Other"), t0).is_some());
+
+        assert!(coalescer.flush_due(t0 + Duration::from_secs(10)).is_empty());
+
+        let digests = coalescer.flush_due(t0 + window);
+        assert_eq!(digests.len(), 1);
+        assert_eq!(digests[0].title, "SQL ERROR ×3421 in last 5 min");
+
+        // Burst over: nothing more to report, and the next alert goes out immediately
+        assert!(coalescer.flush_due(t0 + window * 2).is_empty());
+        assert!(coalescer.offer(alert("SQL ERROR"), t0 + window * 3).is_some());
+    }
+}
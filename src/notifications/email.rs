@@ -0,0 +1,146 @@
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use super::{html_to_text, Notification, Notifier, NotifyError, SmtpSettings, SmtpTls, SEND_TIMEOUT};
+
+/// Plain-text email over SMTP. `tls = "none"` allows a local SMTP sink (MailHog, smtp4dev) in tests.
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, NotifyError> {
+    address.parse().map_err(|e| NotifyError::Config(format!("invalid address '{}': {}", address, e)))
+}
+
+impl EmailNotifier {
+    pub fn new(smtp: &SmtpSettings) -> Result<Self, NotifyError> {
+        let builder = match smtp.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)
+                .map_err(|e| NotifyError::Config(e.to_string()))?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)
+                .map_err(|e| NotifyError::Config(e.to_string()))?,
+        };
+
+        let mut builder = builder.port(smtp.port).timeout(Some(SEND_TIMEOUT));
+        if let (Some(user), Some(password)) = (&smtp.username, &smtp.password) {
+            builder = builder.credentials(Credentials::new(user.clone(), password.clone()));
+        }
+
+        if smtp.to.is_empty() {
+            return Err(NotifyError::Config("email channel needs at least one recipient".into()));
+        }
+
+        Ok(EmailNotifier {
+            transport: builder.build(),
+            from: parse_mailbox(&smtp.from)?,
+            to: smtp.to.iter().map(|a| parse_mailbox(a)).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(format!("[{}] {}", notification.severity.as_str().to_uppercase(), notification.title));
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+
+        let email = builder.body(html_to_text(&notification.message))
+            .map_err(|e| NotifyError::Email(e.to_string()))?;
+
+        self.transport.send(email).await
+            .map(|_| ())
+            .map_err(|e| NotifyError::Email(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::tests::alert;
+    use crate::notifications::Severity;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::sync::oneshot;
+
+    /// Minimal SMTP server accepting one message; returns its port and the
+    /// envelope commands and DATA it received.
+    async fn smtp_server() -> (u16, oneshot::Receiver<(Vec<String>, String)>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let (mut commands, mut data) = (Vec::new(), String::new());
+            writer.write_all(b"220 localhost ESMTP test\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = match line.split(' ').next().unwrap_or("").to_uppercase().as_str() {
+                    "EHLO" | "HELO" => b"250 localhost\r\n",
+                    "DATA" => {
+                        writer.write_all(b"354 end with .\r\n").await.unwrap();
+                        while let Some(line) = lines.next_line().await.unwrap() {
+                            if line == "." {
+                                break;
+                            }
+                            data += &line;
+                            data += "\n";
+                        }
+                        b"250 queued\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 ok\r\n",
+                };
+                commands.push(line);
+                writer.write_all(reply).await.unwrap();
+            }
+            let _ = tx.send((commands, data));
+        });
+        (port, rx)
+    }
+
+    fn smtp(port: u16, to: Vec<String>) -> SmtpSettings {
+        SmtpSettings {
+            host: "127.0.0.1".to_string(),
+            port,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            from: "Teltonika <alerts@example.com>".to_string(),
+            to,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_over_smtp() {
+        let (port, received) = smtp_server().await;
+        let notifier = EmailNotifier::new(&smtp(port, vec!["ops@example.com".to_string(), "oncall@example.com".to_string()])).unwrap();
+        notifier.send(&alert(Severity::Critical)).await.unwrap();
+        // The transport pools connections: dropping it sends QUIT
+        drop(notifier);
+
+        let (commands, data) = received.await.unwrap();
+        assert!(commands.iter().any(|c| c == "MAIL FROM:<alerts@example.com>"), "{:?}", commands);
+        assert!(commands.iter().any(|c| c == "RCPT TO:<ops@example.com>"));
+        assert!(commands.iter().any(|c| c == "RCPT TO:<oncall@example.com>"));
+        assert!(data.contains("Subject: [CRITICAL] (test) SQL ERROR\n"), "{}", data);
+        assert!(data.contains("To: ops@example.com, oncall@example.com\n"), "{}", data);
+        assert!(data.ends_with("\n\nError message:\nboom\n"), "{}", data);
+    }
+
+    #[test]
+    fn test_needs_a_recipient() {
+        assert!(matches!(EmailNotifier::new(&smtp(25, vec![])), Err(NotifyError::Config(_))));
+    }
+}
@@ -0,0 +1,380 @@
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+use crate::config::{get_settings, Settings};
+
+pub mod dispatcher;
+pub mod email;
+pub mod slack;
+pub mod teams;
+pub mod webhook;
+
+pub use email::EmailNotifier;
+pub use slack::SlackNotifier;
+pub use teams::TeamsNotifier;
+pub use webhook::JsonWebhookNotifier;
+
+/// Per-request limit for one channel; channels are sent one after another,
+/// so a hung endpoint must not hold back the others.
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub severity: Severity,
+    /// Already prefixed with the environment, e.g. "(production) SQL ERROR".
+    pub title: String,
+    /// May contain the light HTML Teams understands (`<b>`, `<br/>`).
+    pub message: String,
+}
+
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("unexpected HTTP status {0}")]
+    Status(reqwest::StatusCode),
+    #[error("email failed: {0}")]
+    Email(String),
+    #[error("invalid channel config: {0}")]
+    Config(String),
+}
+
+/// One alerting channel (Teams, Slack, JSON webhook, email...).
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelKind {
+    Teams,
+    Slack,
+    Webhook,
+    Email,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    None,
+    #[default]
+    Starttls,
+    Tls,
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChannelSettings {
+    pub name: String,
+    pub kind: ChannelKind,
+    /// Lowest severity routed to this channel.
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Incoming webhook URL (teams, slack, webhook).
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Extra headers (webhook only).
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// SMTP server and recipients (email only).
+    #[serde(default)]
+    pub smtp: Option<SmtpSettings>,
+}
+
+impl ChannelSettings {
+    fn required_url(&self) -> Result<String, NotifyError> {
+        self.url.clone().filter(|u| !u.is_empty())
+            .ok_or_else(|| NotifyError::Config(format!("channel '{}' needs a url", self.name)))
+    }
+
+    pub fn build(&self) -> Result<Box<dyn Notifier>, NotifyError> {
+        Ok(match self.kind {
+            ChannelKind::Teams => Box::new(TeamsNotifier::new(self.required_url()?)),
+            ChannelKind::Slack => Box::new(SlackNotifier::new(self.required_url()?)),
+            ChannelKind::Webhook => Box::new(JsonWebhookNotifier::new(self.required_url()?, self.headers.clone())),
+            ChannelKind::Email => {
+                let smtp = self.smtp.as_ref()
+                    .ok_or_else(|| NotifyError::Config(format!("channel '{}' needs an [smtp] section", self.name)))?;
+                Box::new(EmailNotifier::new(smtp)?)
+            }
+        })
+    }
+}
+
+struct Channel {
+    name: String,
+    min_severity: Severity,
+    notifier: Box<dyn Notifier>,
+}
+
+/// The legacy `webhook.teams_url` stays a Teams channel receiving everything.
+fn channel_settings(settings: &Settings) -> Vec<ChannelSettings> {
+    let legacy_teams = Some(&settings.webhook.teams_url)
+        .filter(|url| !url.is_empty())
+        .map(|url| ChannelSettings {
+            name: "teams".to_string(),
+            kind: ChannelKind::Teams,
+            min_severity: Severity::Info,
+            url: Some(url.clone()),
+            headers: HashMap::new(),
+            smtp: None,
+        });
+
+    legacy_teams.into_iter().chain(settings.notifications.channels.iter().cloned()).collect()
+}
+
+fn build_channels(settings: &Settings) -> Vec<Channel> {
+    channel_settings(settings).into_iter()
+        .filter_map(|cfg| match cfg.build() {
+            Ok(notifier) => Some(Channel { name: cfg.name, min_severity: cfg.min_severity, notifier }),
+            Err(e) => {
+                error!("Skipping notification channel '{}': {}", cfg.name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+static CHANNELS: OnceLock<ArcSwap<Vec<Channel>>> = OnceLock::new();
+
+fn channels() -> &'static ArcSwap<Vec<Channel>> {
+    CHANNELS.get_or_init(|| ArcSwap::from_pointee(build_channels(&get_settings())))
+}
+
+/// Rebuild the channels after a config reload; alerts already queued use the new set.
+pub fn reload_channels(settings: &Settings) {
+    channels().store(Arc::new(build_channels(settings)));
+}
+
+/// Strip the light HTML used in messages for channels that only take text.
+pub fn html_to_text(html: &str) -> String {
+    let with_breaks = html.replace("<br/>", "\n").replace("<br>", "\n");
+    let mut out = String::with_capacity(with_breaks.len());
+    let mut in_tag = false;
+    for c in with_breaks.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Channels whose `min_severity` lets `severity` through.
+fn routed(channels: &[Channel], severity: Severity) -> impl Iterator<Item = &Channel> {
+    channels.iter().filter(move |c| severity >= c.min_severity)
+}
+
+/// Send to every channel whose `min_severity` allows it. Only called by the dispatcher.
+async fn deliver(notification: &Notification) {
+    let channels = channels().load_full();
+    for channel in routed(&channels, notification.severity) {
+        match channel.notifier.send(notification).await {
+            Ok(()) => metrics::counter!("notifications_sent_total", "channel" => channel.name.clone()).increment(1),
+            Err(e) => {
+                metrics::counter!("notifications_failed_total", "channel" => channel.name.clone()).increment(1);
+                warn!("Failed to send notification to '{}': {}", channel.name, e);
+            }
+        }
+    }
+}
+
+static DISPATCHER: OnceLock<mpsc::Sender<Notification>> = OnceLock::new();
+
+/// Start the background dispatcher on first use (must be called from the Tokio runtime).
+fn dispatcher() -> &'static mpsc::Sender<Notification> {
+    DISPATCHER.get_or_init(|| {
+        let settings = &get_settings().notifications;
+        let (tx, rx) = mpsc::channel(settings.queue_size);
+        tokio::spawn(dispatcher::run(rx, Duration::from_secs(settings.coalesce_window_secs)));
+        tx
+    })
+}
+
+pub struct NotificationService;
+
+impl NotificationService {
+    /// Queue an alert for the background dispatcher; never waits on the network.
+    pub fn notify(severity: Severity, title: &str, message: &str) {
+        let settings = get_settings();
+        let notification = Notification {
+            severity,
+            title: format!("({}) {}", settings.env, title),
+            message: message.to_string(),
+        };
+
+        if dispatcher().try_send(notification).is_err() {
+            metrics::counter!("notifications_dropped_total").increment(1);
+            warn!("Notification queue full, dropping alert: {}", title);
+        }
+    }
+
+    /// Deliver right away, bypassing the queue and coalescing. Used for the last
+    /// message on shutdown, when the background dispatcher may not run again.
+    pub async fn notify_now(severity: Severity, title: &str, message: &str) {
+        let notification = Notification {
+            severity,
+            title: format!("({}) {}", get_settings().env, title),
+            message: message.to_string(),
+        };
+        deliver(&notification).await;
+    }
+
+    pub fn note(title: &str, message: &str) {
+        Self::notify(Severity::Info, title, message);
+    }
+
+    pub fn sql_error(sql: &str, error: &str) {
+        let settings = get_settings();
+        let env = &settings.env;
+        
+        // Update to env contains dev
+        if env.contains("dev") {
+             error!("SQL Error: {}", error);
+             return; 
+        }
+
+        let message = format!(
+            "<b>Error message</b>:<br/>{}<br/><br/><b>Sql:</b><br/>{}<br/>",
+            error, sql
+        );
+        
+        Self::notify(Severity::Error, "SQL ERROR", &message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn test_html_to_text() {
+        assert_eq!(
+            html_to_text("<b>Error message</b>:<br/>boom<br/><br/><b>Sql:</b><br/>SELECT 1<br/>"),
+            "Error message:\nboom\n\nSql:\nSELECT 1\n"
+        );
+        assert_eq!(html_to_text("plain"), "plain");
+    }
+
+    /// Request received by `endpoint`: request line and headers (lowercased), and the JSON body.
+    pub(super) struct Captured {
+        pub head: String,
+        pub body: serde_json::Value,
+    }
+
+    /// One-shot HTTP endpoint answering `status`; returns its URL and the request it received.
+    pub(super) async fn endpoint(status: &'static str) -> (String, oneshot::Receiver<Captured>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            let head_end = loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+            };
+            let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+            let length: usize = head.lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map(|v| v.trim().parse().unwrap())
+                .unwrap_or(0);
+            while request.len() < head_end + length {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+            let _ = socket.write_all(response.as_bytes()).await;
+            let body = serde_json::from_slice(&request[head_end..]).unwrap();
+            let _ = tx.send(Captured { head, body });
+        });
+        (url, rx)
+    }
+
+    pub(super) fn alert(severity: Severity) -> Notification {
+        Notification {
+            severity,
+            title: "(test) SQL ERROR".to_string(),
+            message: "<b>Error message</b>:<br/>boom".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_severity_routing() {
+        let channel = |name: &str, min_severity: Severity| ChannelSettings {
+            name: name.to_string(),
+            kind: ChannelKind::Webhook,
+            min_severity,
+            url: Some(format!("https://alerts.example.com/{}", name)),
+            headers: HashMap::new(),
+            smtp: None,
+        };
+        let mut settings = (*get_settings()).clone();
+        settings.webhook.teams_url = "https://teams.example.com/hook".to_string();
+        settings.notifications.channels = vec![
+            channel("ops", Severity::Warning),
+            channel("oncall", Severity::Critical),
+        ];
+        let channels = build_channels(&settings);
+
+        let names = |severity| routed(&channels, severity).map(|c| c.name.as_str()).collect::<Vec<_>>();
+        // The legacy Teams URL receives everything
+        assert_eq!(names(Severity::Info), vec!["teams"]);
+        assert_eq!(names(Severity::Warning), vec!["teams", "ops"]);
+        assert_eq!(names(Severity::Error), vec!["teams", "ops"]);
+        assert_eq!(names(Severity::Critical), vec!["teams", "ops", "oncall"]);
+    }
+}
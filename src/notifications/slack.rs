@@ -0,0 +1,58 @@
+use async_trait::async_trait;
+use serde_json::json;
+
+use super::{html_to_text, Notification, Notifier, NotifyError, SEND_TIMEOUT};
+use crate::webhook::http_client;
+
+/// Slack-compatible incoming webhook (also works with Mattermost and Rocket.Chat).
+pub struct SlackNotifier {
+    url: String,
+}
+
+impl SlackNotifier {
+    pub fn new(url: String) -> Self {
+        SlackNotifier { url }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let mut text = format!("*[{}] {}*", notification.severity.as_str().to_uppercase(), notification.title);
+        let message = html_to_text(&notification.message);
+        if !message.trim().is_empty() {
+            text += &format!("\n{}", message);
+        }
+
+        let res = http_client().post(&self.url).json(&json!({ "text": text })).timeout(SEND_TIMEOUT).send().await?;
+        if !res.status().is_success() {
+            return Err(NotifyError::Status(res.status()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::tests::{alert, endpoint};
+    use crate::notifications::Severity;
+
+    #[tokio::test]
+    async fn test_text_payload() {
+        let (url, request) = endpoint("200 OK").await;
+        SlackNotifier::new(url).send(&alert(Severity::Error)).await.unwrap();
+
+        let request = request.await.unwrap();
+        assert!(request.head.starts_with("post /hook "));
+        assert!(request.head.contains("content-type: application/json"));
+        assert_eq!(request.body, json!({ "text": "*[ERROR] (test) SQL ERROR*\nError message:\nboom" }));
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let (url, _request) = endpoint("404 Not Found").await;
+        let err = SlackNotifier::new(url).send(&alert(Severity::Error)).await.unwrap_err();
+        assert!(matches!(err, NotifyError::Status(status) if status == 404), "{}", err);
+    }
+}
@@ -0,0 +1,93 @@
+use async_trait::async_trait;
+use serde_json::json;
+
+use super::{Notification, Notifier, NotifyError, Severity, SEND_TIMEOUT};
+use crate::webhook::http_client;
+
+/// Microsoft Teams incoming webhook (MessageCard).
+pub struct TeamsNotifier {
+    url: String,
+}
+
+impl TeamsNotifier {
+    pub fn new(url: String) -> Self {
+        TeamsNotifier { url }
+    }
+}
+
+fn theme_color(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "0078D7",
+        Severity::Warning => "FFA500",
+        Severity::Error | Severity::Critical => "D13438",
+    }
+}
+
+#[async_trait]
+impl Notifier for TeamsNotifier {
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let payload = json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": notification.title,
+            "title": notification.title,
+            "themeColor": theme_color(notification.severity),
+            "sections": [{
+                "text": notification.message
+            }]
+        });
+
+        let res = http_client().post(&self.url).json(&payload).timeout(SEND_TIMEOUT).send().await?;
+        if !res.status().is_success() {
+            return Err(NotifyError::Status(res.status()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::tests::{alert, endpoint};
+
+    #[tokio::test]
+    async fn test_message_card() {
+        let (url, request) = endpoint("200 OK").await;
+        TeamsNotifier::new(url).send(&alert(Severity::Warning)).await.unwrap();
+
+        let request = request.await.unwrap();
+        assert!(request.head.starts_with("post /hook "));
+        assert!(request.head.contains("content-type: application/json"));
+        assert_eq!(request.body, json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": "(test) SQL ERROR",
+            "title": "(test) SQL ERROR",
+            "themeColor": "FFA500",
+            "sections": [{ "text": "<b>Error message</b>:<br/>boom" }]
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let (url, _request) = endpoint("500 Internal Server Error").await;
+        let err = TeamsNotifier::new(url).send(&alert(Severity::Error)).await.unwrap_err();
+        assert!(matches!(err, NotifyError::Status(status) if status == 500), "{}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_timeout() {
+        // Accepts the connection and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let started = tokio::time::Instant::now();
+        let err = TeamsNotifier::new(url).send(&alert(Severity::Error)).await.unwrap_err();
+        assert!(matches!(&err, NotifyError::Http(e) if e.is_timeout()), "{}", err);
+        assert!(started.elapsed() >= SEND_TIMEOUT);
+    }
+}
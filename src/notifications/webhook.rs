@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use serde_json::json;
+use std::collections::HashMap;
+
+use super::{html_to_text, Notification, Notifier, NotifyError, SEND_TIMEOUT};
+use crate::webhook::http_client;
+
+/// Generic JSON POST for tools that are neither Teams nor Slack (PagerDuty bridges, n8n...).
+pub struct JsonWebhookNotifier {
+    url: String,
+    headers: HashMap<String, String>,
+}
+
+impl JsonWebhookNotifier {
+    pub fn new(url: String, headers: HashMap<String, String>) -> Self {
+        JsonWebhookNotifier { url, headers }
+    }
+}
+
+#[async_trait]
+impl Notifier for JsonWebhookNotifier {
+    async fn send(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let payload = json!({
+            "severity": notification.severity.as_str(),
+            "title": notification.title,
+            "message": html_to_text(&notification.message),
+            "timestamp": chrono::Utc::now(),
+        });
+
+        let mut req = http_client().post(&self.url).json(&payload).timeout(SEND_TIMEOUT);
+        for (name, value) in &self.headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+
+        let res = req.send().await?;
+        if !res.status().is_success() {
+            return Err(NotifyError::Status(res.status()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::tests::{alert, endpoint};
+    use crate::notifications::Severity;
+
+    #[tokio::test]
+    async fn test_json_payload_and_headers() {
+        let (url, request) = endpoint("202 Accepted").await;
+        let headers = HashMap::from([("X-Api-Key".to_string(), "k3y".to_string())]);
+        let before = chrono::Utc::now();
+        JsonWebhookNotifier::new(url, headers).send(&alert(Severity::Critical)).await.unwrap();
+
+        let mut request = request.await.unwrap();
+        assert!(request.head.starts_with("post /hook "));
+        assert!(request.head.contains("content-type: application/json"));
+        assert!(request.head.contains("x-api-key: k3y"));
+        let timestamp: chrono::DateTime<chrono::Utc> = serde_json::from_value(request.body["timestamp"].take()).unwrap();
+        assert!(timestamp >= before);
+        assert_eq!(request.body, json!({
+            "severity": "critical",
+            "title": "(test) SQL ERROR",
+            "message": "Error message:\nboom",
+            "timestamp": null
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let (url, _request) = endpoint("503 Service Unavailable").await;
+        let err = JsonWebhookNotifier::new(url, HashMap::new()).send(&alert(Severity::Error)).await.unwrap_err();
+        assert!(matches!(err, NotifyError::Status(status) if status == 503), "{}", err);
+    }
+}
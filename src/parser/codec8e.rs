@@ -1,7 +1,7 @@
 use bytes::{Buf, Bytes};
 use chrono::{TimeZone, Utc};
 use super::models::{AvlRecord, TeltonikaGps, IoGroup, IoElement};
-use super::io_elements::get_io_element_definition;
+use super::io_elements::{get_io_element_definition, get_io_element_override};
 
 const GPS_PRECISION: f64 = 10000000.0;
 
@@ -156,6 +156,15 @@ fn parse_io_elements(buf: &mut Bytes) -> Result<IoGroup, Box<dyn std::error::Err
 }
 
 fn resolve_io_meta(id: u16, value: i64) -> (String, Option<String>, Option<String>) {
+    if let Some(o) = get_io_element_override(id) {
+        let value_human = if o.values.is_empty() {
+            Some("".to_string())
+        } else {
+            o.values.get(&value.to_string()).cloned()
+        };
+        return (o.label, o.dimension, value_human);
+    }
+
     let def = get_io_element_definition(id);
     match def {
         Some(d) => {
@@ -1,4 +1,7 @@
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
 
 #[derive(Debug, Clone)]
 pub struct IoElementDefinition {
@@ -7,6 +10,35 @@ pub struct IoElementDefinition {
     pub values: Option<HashMap<i64, &'static str>>,
 }
 
+/// Config-provided label/unit for an IO id, replacing the built-in definition.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IoElementOverride {
+    pub label: String,
+    #[serde(default)]
+    pub dimension: Option<String>,
+    /// Raw value -> human readable value.
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+static OVERRIDES: OnceLock<ArcSwap<HashMap<u16, IoElementOverride>>> = OnceLock::new();
+
+fn overrides() -> &'static ArcSwap<HashMap<u16, IoElementOverride>> {
+    OVERRIDES.get_or_init(|| ArcSwap::from_pointee(HashMap::new()))
+}
+
+/// Replace the catalog overrides (config `io_catalog`); keys that are not IO ids are ignored.
+pub fn set_overrides(catalog: &HashMap<String, IoElementOverride>) {
+    let parsed = catalog.iter()
+        .filter_map(|(id, entry)| Some((id.parse::<u16>().ok()?, entry.clone())))
+        .collect();
+    overrides().store(Arc::new(parsed));
+}
+
+pub fn get_io_element_override(id: u16) -> Option<IoElementOverride> {
+    overrides().load().get(&id).cloned()
+}
+
 pub fn get_io_element_definition(id: u16) -> Option<IoElementDefinition> {
     match id {
         1 => Some(IoElementDefinition {
@@ -6,30 +6,103 @@ pub mod codec8e;
 pub mod io_elements;
 pub mod models;
 
+/// Why a packet was rejected (set together with `invalid`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// Shorter than an AVL header.
+    TooShort,
+    UnsupportedCodec(u8),
+    /// Records cut short or not matching the header.
+    Malformed,
+    /// Handshake frame whose IMEI is not 15 digits with a valid check digit (why).
+    InvalidImei(&'static str),
+}
+
+impl ParseError {
+    /// Label used for the `parse_errors_total` metric.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::TooShort => "too_short",
+            ParseError::UnsupportedCodec(_) => "unsupported_codec",
+            ParseError::Malformed => "malformed",
+            ParseError::InvalidImei(_) => "invalid_imei",
+        }
+    }
+}
+
+/// 15 ASCII digits whose last one is the Luhn check digit, or with
+/// `allow_imeisv` also a 16-digit IMEISV (no check digit).
+pub fn validate_imei(imei: &[u8], allow_imeisv: bool) -> Result<(), &'static str> {
+    if !imei.iter().all(u8::is_ascii_digit) {
+        return Err("not all digits");
+    }
+    match imei.len() {
+        15 if luhn_valid(imei) => Ok(()),
+        15 => Err("bad check digit"),
+        16 if allow_imeisv => Ok(()),
+        _ => Err("wrong length"),
+    }
+}
+
+fn luhn_valid(digits: &[u8]) -> bool {
+    let sum: u32 = digits.iter().rev().enumerate()
+        .map(|(i, d)| {
+            let d = (d - b'0') as u32;
+            match i % 2 {
+                0 => d,
+                _ if d * 2 > 9 => d * 2 - 9,
+                _ => d * 2,
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Teltonika name of a codec id (0x08 -> "8", 0x8E -> "8E").
+pub fn codec_name(codec_id: u8) -> String {
+    match codec_id {
+        0x8E => "8E".to_string(),
+        0x08 => "8".to_string(),
+        0x10 => "16".to_string(),
+        0x0C => "12".to_string(),
+        0x0D => "13".to_string(),
+        0x0E => "14".to_string(),
+        other => format!("{:#04x}", other),
+    }
+}
+
 pub struct TeltonikaParser {
     pub is_imei: bool,
     pub imei: Option<String>,
     pub avl_data: Option<AvlData>,
     pub invalid: bool,
+    pub error: Option<ParseError>,
 }
 
 impl TeltonikaParser {
-    pub fn new(mut buf: Bytes) -> Self {
+    fn rejected(error: ParseError) -> Self {
+        TeltonikaParser { is_imei: false, imei: None, avl_data: None, invalid: true, error: Some(error) }
+    }
+
+    /// `allow_imeisv` also accepts 16-digit IMEISVs in the handshake (`devices.allow_imeisv`).
+    pub fn parse(mut buf: Bytes, allow_imeisv: bool) -> Self {
         // Check for IMEI
         // IMEI length is first 2 bytes (u16)
         if buf.len() >= 2 {
             let imei_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
             if imei_len > 0 && buf.len() == imei_len + 2 {
-                let imei_bytes = buf.slice(2..2+imei_len);
-                let imei = String::from_utf8(imei_bytes.to_vec()).ok();
-                if let Some(imei_str) = imei {
-                     return TeltonikaParser {
-                        is_imei: true,
-                        imei: Some(imei_str),
-                        avl_data: None,
-                        invalid: false,
-                    };
+                let imei_bytes = &buf[2..];
+                if let Err(reason) = validate_imei(imei_bytes, allow_imeisv) {
+                    return TeltonikaParser { is_imei: true, ..Self::rejected(ParseError::InvalidImei(reason)) };
                 }
+                return TeltonikaParser {
+                    is_imei: true,
+                    // Only ASCII digits at this point
+                    imei: Some(String::from_utf8_lossy(imei_bytes).into_owned()),
+                    avl_data: None,
+                    invalid: false,
+                    error: None,
+                };
             }
         }
         
@@ -38,14 +111,14 @@ impl TeltonikaParser {
         // Codec ID: 1 byte
         // Number of Data: 1 byte
         if buf.len() < 8 { // Min header size
-             return TeltonikaParser { is_imei: false, imei: None, avl_data: None, invalid: true };
+             return Self::rejected(ParseError::TooShort);
         }
         
         let zeros = buf.slice(0..4);
         if zeros.as_ref() == [0, 0, 0, 0] {
              let _preamble = buf.get_u32(); // consume 0000
              if buf.len() < 4 {
-                 return TeltonikaParser { is_imei: false, imei: None, avl_data: None, invalid: true };
+                 return Self::rejected(ParseError::TooShort);
              }
         }
         
@@ -57,7 +130,7 @@ impl TeltonikaParser {
              // Maybe it skipped 0s and we need to retry?
              // But following JS explicitly:
              warn!("Unsupported codec: {}", codec_id);
-             return TeltonikaParser { is_imei: false, imei: None, avl_data: None, invalid: true };
+             return Self::rejected(ParseError::UnsupportedCodec(codec_id));
         }
         
         let records_res = codec8e::parse(&mut buf, number_of_data);
@@ -72,13 +145,58 @@ impl TeltonikaParser {
                          records,
                      }),
                      invalid: false,
+                     error: None,
                  }
             },
             Err(e) => {
                 error!("Parser error: {}", e);
-                TeltonikaParser { is_imei: false, imei: None, avl_data: None, invalid: true }
+                Self::rejected(ParseError::Malformed)
             }
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_kinds() {
+        let short = TeltonikaParser::parse(Bytes::from_static(&[0, 0, 0, 0, 0, 1]), false);
+        assert_eq!(short.error, Some(ParseError::TooShort));
+
+        // Codec 8 header with one record
+        let codec8 = TeltonikaParser::parse(Bytes::from_static(&[0, 0, 0, 0, 0, 0, 0, 0x36, 0x08, 0x01]), false);
+        assert_eq!(codec8.error, Some(ParseError::UnsupportedCodec(8)));
+        assert_eq!(codec_name(8), "8");
+
+        let truncated = TeltonikaParser::parse(Bytes::from_static(&[0, 0, 0, 0, 0, 0, 0, 0x36, 0x8E, 0x01, 0x00]), false);
+        assert_eq!(truncated.error, Some(ParseError::Malformed));
+        assert_eq!(truncated.error.unwrap().kind(), "malformed");
+    }
+
+    fn handshake(imei: &[u8]) -> Bytes {
+        let mut frame = (imei.len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(imei);
+        Bytes::from(frame)
+    }
+
+    #[test]
+    fn test_imei_validation() {
+        let valid = TeltonikaParser::parse(handshake(b"356307042441013"), false);
+        assert!(valid.is_imei && !valid.invalid);
+        assert_eq!(valid.imei.as_deref(), Some("356307042441013"));
+
+        let bad_check = TeltonikaParser::parse(handshake(b"356307042441014"), false);
+        assert!(bad_check.is_imei && bad_check.invalid);
+        assert_eq!(bad_check.error, Some(ParseError::InvalidImei("bad check digit")));
+
+        let garbage = TeltonikaParser::parse(handshake(b"\x16\x03\x01GET /"), false);
+        assert_eq!(garbage.error, Some(ParseError::InvalidImei("not all digits")));
+
+        let imeisv = handshake(b"3563070424410101");
+        assert_eq!(TeltonikaParser::parse(imeisv.clone(), false).error, Some(ParseError::InvalidImei("wrong length")));
+        assert!(!TeltonikaParser::parse(imeisv, true).invalid);
+    }
+}
@@ -0,0 +1,237 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::config::{get_settings, Settings};
+use crate::notifications::{NotificationService, Severity};
+use crate::sink::TelemetrySink;
+use crate::webhook::{jobs_for_presence, PresencePayload, WebhookQueue, WEBHOOK_SCHEMA_VERSION};
+
+/// A device went silent or reported again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub imei: String,
+    pub online: bool,
+    pub last_seen: DateTime<Utc>,
+    pub expected_interval_secs: u64,
+}
+
+struct DeviceState {
+    last_seen: DateTime<Utc>,
+    offline: bool,
+}
+
+/// Last packet and online/offline flag of every IMEI, seeded from the
+/// latest-state table at startup.
+pub struct Presence {
+    devices: OnceLock<Mutex<HashMap<String, DeviceState>>>,
+}
+
+pub static PRESENCE: Presence = Presence::new();
+
+impl Presence {
+    pub const fn new() -> Self {
+        Presence { devices: OnceLock::new() }
+    }
+
+    fn devices(&self) -> &Mutex<HashMap<String, DeviceState>> {
+        self.devices.get_or_init(Default::default)
+    }
+
+    pub fn seen(&self, imei: &str, at: DateTime<Utc>) {
+        if imei.is_empty() {
+            return;
+        }
+        let mut devices = self.devices().lock().unwrap();
+        match devices.get_mut(imei) {
+            Some(state) => state.last_seen = state.last_seen.max(at),
+            None => {
+                devices.insert(imei.to_string(), DeviceState { last_seen: at, offline: false });
+            }
+        }
+    }
+
+    /// Last-seen times stored before the restart, so devices that went silent
+    /// across it are still flagged. Devices already past their threshold start
+    /// offline without an alert; devices seen since startup are left alone.
+    fn seed(&self, settings: &Settings, last_seen: Vec<(String, DateTime<Utc>)>, now: DateTime<Utc>) {
+        let mut devices = self.devices().lock().unwrap();
+        for (imei, at) in last_seen {
+            let offline = silent_too_long(settings, &imei, at, now);
+            devices.entry(imei).or_insert(DeviceState { last_seen: at, offline });
+        }
+    }
+
+    /// Flip devices whose silence crossed (or no longer crosses) their
+    /// threshold, and refresh the gauges.
+    fn check(&self, settings: &Settings, now: DateTime<Utc>) -> Vec<Transition> {
+        let mut transitions = Vec::new();
+        let mut devices = self.devices().lock().unwrap();
+        for (imei, state) in devices.iter_mut() {
+            let expected_interval_secs = settings.expected_interval_secs_for(imei);
+            let offline = silent_too_long(settings, imei, state.last_seen, now);
+            if offline != state.offline {
+                state.offline = offline;
+                transitions.push(Transition { imei: imei.clone(), online: !offline, last_seen: state.last_seen, expected_interval_secs });
+            }
+        }
+        let offline = devices.values().filter(|s| s.offline).count();
+        metrics::gauge!("devices_offline").set(offline as f64);
+        metrics::gauge!("devices_online").set((devices.len() - offline) as f64);
+        transitions
+    }
+
+    /// Alert and notify `presence` webhook subscribers of one transition.
+    async fn announce(transition: &Transition, settings: &Settings, webhooks: &WebhookQueue, now: DateTime<Utc>) {
+        let silent = (now - transition.last_seen).num_seconds();
+        let (status, event) = if transition.online {
+            info!("📶 IMEI {} is back online (last packet {})", transition.imei, transition.last_seen);
+            NotificationService::notify(Severity::Info, "📶 Device back online",
+                &format!("IMEI {} reports again (last packet {}).", transition.imei, transition.last_seen));
+            ("online", "device_online")
+        } else {
+            warn!("📴 IMEI {} is offline, silent for {}s", transition.imei, silent);
+            NotificationService::notify(Severity::Warning, "📴 Device offline",
+                &format!("IMEI {} has not reported since {} ({}s, expected every {}s).", transition.imei, transition.last_seen, silent, transition.expected_interval_secs));
+            ("offline", "device_offline")
+        };
+        metrics::counter!("device_presence_transitions_total", "status" => status).increment(1);
+
+        let payload = PresencePayload {
+            schema_version: WEBHOOK_SCHEMA_VERSION,
+            event: event.to_string(),
+            imei: transition.imei.clone(),
+            last_seen: transition.last_seen,
+            expected_interval_secs: transition.expected_interval_secs,
+            detected_at: now,
+        };
+        for job in jobs_for_presence(settings, &payload) {
+            webhooks.enqueue(job).await;
+        }
+    }
+
+    pub fn spawn_watcher(&'static self, sink: Arc<dyn TelemetrySink>, webhooks: Arc<WebhookQueue>, shutdown: CancellationToken) {
+        tokio::spawn(async move {
+            let mut seeded = false;
+            loop {
+                let settings = get_settings();
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(settings.presence.check_interval_secs.max(1))) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+                let settings = get_settings();
+                if !settings.presence.enabled {
+                    continue;
+                }
+                if !seeded {
+                    // Retried every check while the database is unreachable
+                    match sink.last_seen().await {
+                        Ok(last_seen) => {
+                            info!("Presence seeded with {} device(s) from the latest-state table", last_seen.len());
+                            self.seed(&settings, last_seen, Utc::now());
+                            seeded = true;
+                        }
+                        Err(e) => warn!("Cannot load last-seen times for presence: {}", e),
+                    }
+                }
+                let now = Utc::now();
+                for transition in self.check(&settings, now) {
+                    Self::announce(&transition, &settings, &webhooks, now).await;
+                }
+            }
+        });
+    }
+}
+
+fn silent_too_long(settings: &Settings, imei: &str, last_seen: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    let threshold = settings.expected_interval_secs_for(imei).saturating_mul(settings.presence.missed_intervals as u64);
+    (now - last_seen).num_seconds() > threshold as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::models::{AvlRecord, IoGroup, TeltonikaGps};
+    use crate::sink::MemorySink;
+
+    #[test]
+    fn test_offline_transitions() {
+        let mut settings = (*get_settings()).clone();
+        settings.presence.expected_interval_secs = 60;
+        settings.presence.missed_intervals = 3;
+        settings.presence.group_intervals_secs = HashMap::from([("sleepers".to_string(), 3600)]);
+        settings.groups = HashMap::from([("sleepers".to_string(), vec!["356307042441021".to_string()])]);
+
+        let presence = Presence::new();
+        let start = Utc::now();
+        presence.seen("356307042441013", start);
+        presence.seen("356307042441021", start);
+        assert!(presence.check(&settings, start + chrono::Duration::seconds(180)).is_empty());
+
+        // Only the device without the long group interval goes offline, once
+        let transitions = presence.check(&settings, start + chrono::Duration::seconds(181));
+        assert_eq!(transitions, vec![Transition { imei: "356307042441013".to_string(), online: false, last_seen: start, expected_interval_secs: 60 }]);
+        assert!(presence.check(&settings, start + chrono::Duration::seconds(600)).is_empty());
+
+        let back = start + chrono::Duration::seconds(700);
+        presence.seen("356307042441013", back);
+        let transitions = presence.check(&settings, back + chrono::Duration::seconds(1));
+        assert_eq!(transitions.len(), 1);
+        assert!(transitions[0].online);
+        assert_eq!(transitions[0].last_seen, back);
+    }
+
+    #[test]
+    fn test_seed_from_storage() {
+        let mut settings = (*get_settings()).clone();
+        settings.presence.expected_interval_secs = 60;
+        settings.presence.missed_intervals = 3;
+
+        let presence = Presence::new();
+        let now = Utc::now();
+        presence.seen("356307042441039", now);
+        presence.seed(&settings, vec![
+            ("356307042441013".to_string(), now - chrono::Duration::seconds(30)),   // silent since just before the restart
+            ("356307042441021".to_string(), now - chrono::Duration::days(30)),      // long gone: offline, no alert
+            ("356307042441039".to_string(), now - chrono::Duration::days(30)),      // already seen since startup
+        ], now);
+
+        assert!(presence.check(&settings, now).is_empty());
+        let transitions = presence.check(&settings, now + chrono::Duration::seconds(151));
+        assert_eq!(transitions.len(), 1);
+        assert_eq!((transitions[0].imei.as_str(), transitions[0].online), ("356307042441013", false));
+    }
+
+    #[tokio::test]
+    async fn test_seed_uses_receive_time() {
+        let mut settings = (*get_settings()).clone();
+        settings.presence.expected_interval_secs = 60;
+        settings.presence.missed_intervals = 3;
+
+        // GPS clock a day behind: last_seen must be the receive time, like the SQL backends' updated_at
+        let sink = MemorySink::new();
+        let record = AvlRecord {
+            timestamp: Utc::now() - chrono::Duration::days(1),
+            priority: 0,
+            gps: TeltonikaGps { longitude: 5.374, latitude: 43.2951, altitude: 0, angle: 0, satellites: 8, speed: 0 },
+            event_id: 0,
+            io_groups: IoGroup { n1: vec![], n2: vec![], n4: vec![], n8: vec![], nx: vec![] },
+            io_elements: vec![],
+            properties_count: 0,
+        };
+        let before = Utc::now();
+        sink.upsert_state("356307042441047", &record).await.unwrap();
+        let last_seen = sink.last_seen().await.unwrap();
+        assert_eq!(last_seen.len(), 1);
+        assert!(last_seen[0].1 >= before);
+
+        let presence = Presence::new();
+        let now = Utc::now();
+        presence.seed(&settings, last_seen, now);
+        assert!(presence.check(&settings, now).is_empty());
+        assert!(!presence.devices().lock().unwrap()["356307042441047"].offline);
+    }
+}
@@ -0,0 +1,218 @@
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::config::{reload_settings, subscribe_changes, Settings};
+use crate::devices;
+use crate::logging::{self, FilterHandle};
+use crate::notifications::{self, NotificationService, Severity};
+use crate::parser::io_elements;
+
+/// Re-read the configuration and report the outcome (SIGHUP and `/admin/reload`).
+pub fn reload() -> Result<(), Vec<String>> {
+    match reload_settings() {
+        Ok(_) => Ok(()),
+        Err(problems) => {
+            for problem in &problems {
+                error!("Config reload rejected: {}", problem);
+            }
+            NotificationService::notify(Severity::Warning, "⚠️ Config reload rejected", &problems.join("<br/>"));
+            Err(problems)
+        }
+    }
+}
+
+/// Reload on SIGHUP.
+#[cfg(unix)]
+pub fn spawn_signal_handler() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Cannot listen for SIGHUP, config reload only via /admin/reload: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading configuration");
+            let _ = reload();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_signal_handler() {}
+
+/// Push reloaded settings into the parts that cache them.
+///
+/// Webhook subscribers and inactivity timeouts read the settings on every use
+/// and need nothing here; existing connections are never touched.
+pub fn spawn_appliers(filter: FilterHandle, connections: Arc<Semaphore>, settings: &Settings) {
+    apply_catalogs(settings);
+
+    let mut changes = subscribe_changes();
+    let mut max_connections = settings.server.max_connections;
+    let retiring = Arc::new(Mutex::new(Retiring::default()));
+    tokio::spawn(async move {
+        while changes.changed().await.is_ok() {
+            let settings = changes.borrow_and_update().clone();
+
+            logging::apply(&filter, &settings);
+            notifications::reload_channels(&settings);
+            apply_catalogs(&settings);
+            resize_limit(&connections, &retiring, max_connections, settings.server.max_connections);
+            max_connections = settings.server.max_connections;
+        }
+    });
+}
+
+/// IO element overrides and the IMEI allowlist, both cached outside the settings.
+fn apply_catalogs(settings: &Settings) {
+    io_elements::set_overrides(&settings.io_catalog);
+    devices::load_allowlist(&settings.devices);
+}
+
+/// Permits a shrink could not take yet because connections held them.
+#[derive(Default)]
+struct Retiring {
+    deficit: usize,
+    /// Cancels the task retiring them once a grow has paid the deficit.
+    task: Option<CancellationToken>,
+}
+
+/// Grow or shrink the connection semaphore. Shrinking never drops a connection:
+/// permits still held are retired as those connections close, and a later grow
+/// cancels that debt before adding permits.
+fn resize_limit(connections: &Arc<Semaphore>, retiring: &Arc<Mutex<Retiring>>, old: usize, new: usize) {
+    let mut state = retiring.lock().unwrap();
+    if new > old {
+        let paid = state.deficit.min(new - old);
+        state.deficit -= paid;
+        connections.add_permits(new - old - paid);
+        if state.deficit == 0 {
+            if let Some(task) = state.task.take() {
+                task.cancel();
+            }
+        }
+    } else if new < old {
+        state.deficit += (old - new) - connections.forget_permits(old - new);
+        if state.deficit > 0 && state.task.is_none() {
+            let cancel = CancellationToken::new();
+            state.task = Some(cancel.clone());
+            tokio::spawn(retire(connections.clone(), retiring.clone(), cancel));
+        }
+    }
+    if new != old {
+        info!("Connection limit changed from {} to {}", old, new);
+    }
+}
+
+/// Forget permits one at a time as connections release them, until the deficit is paid.
+async fn retire(connections: Arc<Semaphore>, retiring: Arc<Mutex<Retiring>>, cancel: CancellationToken) {
+    loop {
+        let permit = tokio::select! {
+            permit = connections.clone().acquire_owned() => match permit {
+                Ok(permit) => permit,
+                Err(_) => return,
+            },
+            _ = cancel.cancelled() => return,
+        };
+        let mut state = retiring.lock().unwrap();
+        // Cancelled while acquiring: the permit goes back to the pool
+        if cancel.is_cancelled() {
+            return;
+        }
+        permit.forget();
+        state.deficit -= 1;
+        if state.deficit == 0 {
+            state.task = None;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_settings;
+    use crate::parser::codec8e;
+    use crate::parser::io_elements::IoElementOverride;
+    use bytes::Bytes;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_resize_limit() {
+        let connections = Arc::new(Semaphore::new(4));
+        let retiring = Arc::new(Mutex::new(Retiring::default()));
+        let held = connections.clone().acquire_many_owned(3).await.unwrap();
+
+        resize_limit(&connections, &retiring, 4, 6);
+        assert_eq!(connections.available_permits(), 3);
+
+        // Only 3 free: 3 are retired now, the last one once a connection closes
+        resize_limit(&connections, &retiring, 6, 2);
+        assert_eq!(connections.available_permits(), 0);
+        drop(held);
+        tokio::task::yield_now().await;
+        assert_eq!(connections.available_permits(), 2);
+        assert!(retiring.lock().unwrap().task.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resize_limit_shrink_then_grow() {
+        let connections = Arc::new(Semaphore::new(10));
+        let retiring = Arc::new(Mutex::new(Retiring::default()));
+        let held = connections.clone().acquire_many_owned(8).await.unwrap();
+
+        // 6 permits still owed when the limit goes back up: the debt is cancelled
+        resize_limit(&connections, &retiring, 10, 2);
+        assert_eq!(connections.available_permits(), 0);
+        resize_limit(&connections, &retiring, 2, 10);
+        assert_eq!(connections.available_permits(), 2);
+        assert_eq!(retiring.lock().unwrap().deficit, 0);
+
+        drop(held);
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(connections.available_permits(), 10);
+    }
+
+    #[test]
+    fn test_io_catalog_reload() {
+        // One record carrying a single 1-byte IO with an id no built-in definition uses
+        let record = || {
+            let mut data = 1_700_000_000_000i64.to_be_bytes().to_vec();
+            data.push(0); // priority
+            data.extend_from_slice(&[0; 15]); // GPS
+            data.extend_from_slice(&0u16.to_be_bytes()); // event id
+            data.extend_from_slice(&1u16.to_be_bytes()); // properties count
+            data.extend_from_slice(&1u16.to_be_bytes()); // N1 count
+            data.extend_from_slice(&10010u16.to_be_bytes());
+            data.push(1);
+            for _ in 0..4 {
+                data.extend_from_slice(&0u16.to_be_bytes()); // N2, N4, N8, NX counts
+            }
+            let records = codec8e::parse(&mut Bytes::from(data), 1).unwrap();
+            records[0].io_groups.n1[0].clone()
+        };
+        assert_eq!(record().label, "Unknown-10010");
+
+        let mut settings = (*get_settings()).clone();
+        settings.io_catalog.insert("10010".to_string(), IoElementOverride {
+            label: "Bilge pump".to_string(),
+            dimension: None,
+            values: HashMap::from([("1".to_string(), "On".to_string())]),
+        });
+        apply_catalogs(&settings);
+        let io = record();
+        assert_eq!((io.label.as_str(), io.value_human.as_deref()), ("Bilge pump", Some("On")));
+
+        // Removed from the config: back to the built-in catalog
+        apply_catalogs(&get_settings());
+        assert_eq!(record().label, "Unknown-10010");
+    }
+}
@@ -0,0 +1,266 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::sink::SessionSummary;
+
+/// Lifecycle of one device connection: the IMEI handshake must come first,
+/// then only AVL data is accepted until the connection closes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    AwaitingImei,
+    Authenticated,
+    Closing,
+}
+
+/// Frame not allowed in the current state; the connection is closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    DataBeforeImei,
+    SecondImei,
+}
+
+impl ProtocolError {
+    /// Label used for the `protocol_errors_total` metric.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProtocolError::DataBeforeImei => "data_before_imei",
+            ProtocolError::SecondImei => "second_imei",
+        }
+    }
+}
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+pub struct Session {
+    id: u64,
+    peer: SocketAddr,
+    state: SessionState,
+    imei: String,
+    connected_at: DateTime<Utc>,
+    started: Instant,
+    packets: i64,
+    records: i64,
+    bytes_received: i64,
+    bytes_sent: i64,
+}
+
+impl Session {
+    pub fn new(peer: SocketAddr) -> Self {
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        Session {
+            id,
+            peer,
+            state: SessionState::AwaitingImei,
+            imei: String::new(),
+            connected_at: Utc::now(),
+            started: Instant::now(),
+            packets: 0,
+            records: 0,
+            bytes_received: 0,
+            bytes_sent: 0,
+        }
+    }
+
+    pub fn connected_at(&self) -> DateTime<Utc> {
+        self.connected_at
+    }
+
+    pub fn received(&mut self, bytes: usize) {
+        self.bytes_received += bytes as i64;
+    }
+
+    pub fn sent(&mut self, bytes: usize) {
+        self.bytes_sent += bytes as i64;
+    }
+
+    /// An AVL packet was decoded and stored.
+    pub fn packet(&mut self, records: usize) {
+        self.packets += 1;
+        self.records += records as i64;
+    }
+
+    /// Traffic so far, for the `device_sessions` row.
+    pub fn summary(&self, reason: &str) -> SessionSummary {
+        SessionSummary {
+            disconnected_at: Utc::now(),
+            reason: reason.to_string(),
+            packets: self.packets,
+            records: self.records,
+            bytes_received: self.bytes_received,
+            bytes_sent: self.bytes_sent,
+            duration_ms: self.started.elapsed().as_millis() as i64,
+        }
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    /// Empty until authenticated.
+    pub fn imei(&self) -> &str {
+        &self.imei
+    }
+
+    /// An IMEI frame arrived: only valid as the first frame.
+    pub fn expect_imei(&self) -> Result<(), ProtocolError> {
+        match self.state {
+            SessionState::AwaitingImei => Ok(()),
+            _ => Err(ProtocolError::SecondImei),
+        }
+    }
+
+    /// An AVL packet arrived: only valid after the handshake.
+    pub fn expect_data(&self) -> Result<(), ProtocolError> {
+        match self.state {
+            SessionState::Authenticated => Ok(()),
+            _ => Err(ProtocolError::DataBeforeImei),
+        }
+    }
+
+    /// The IMEI was accepted (valid and allowed by the device registry).
+    pub fn authenticate(&mut self, imei: String) {
+        debug_assert_eq!(self.state, SessionState::AwaitingImei);
+        self.imei = imei;
+        self.transition(SessionState::Authenticated, "handshake accepted");
+    }
+
+    pub fn close(&mut self, reason: &str) {
+        if self.state != SessionState::Closing {
+            self.transition(SessionState::Closing, reason);
+        }
+    }
+
+    fn transition(&mut self, to: SessionState, reason: &str) {
+        info!("{} [{}] {:?} -> {:?} ({})", self.peer, self.imei, self.state, to, reason);
+        self.state = to;
+    }
+}
+
+/// What to do when an IMEI authenticates while it still has a live session
+/// (`devices.duplicate_sessions`), typically a tracker reconnecting after a
+/// NAT timeout while the old socket lingers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Close the older session; the new one becomes the live one.
+    CloseOld,
+    /// Refuse the new connection with a 0x00 handshake reply.
+    RejectNew,
+    /// Keep both; the newest is the live one.
+    Allow,
+}
+
+struct LiveSession {
+    id: u64,
+    peer: SocketAddr,
+    close: CancellationToken,
+}
+
+/// Authenticated sessions by IMEI, always pointing at the newest socket.
+pub struct SessionRegistry {
+    live: OnceLock<Mutex<HashMap<String, LiveSession>>>,
+}
+
+pub static SESSIONS: SessionRegistry = SessionRegistry::new();
+
+impl SessionRegistry {
+    pub const fn new() -> Self {
+        SessionRegistry { live: OnceLock::new() }
+    }
+
+    fn live(&self) -> &Mutex<HashMap<String, LiveSession>> {
+        self.live.get_or_init(Default::default)
+    }
+
+    /// Make `session` the live one for `imei`; `close` is cancelled if a newer
+    /// session replaces it. Returns false when `policy` refuses it.
+    pub fn register(&self, imei: &str, session: &Session, close: CancellationToken, policy: DuplicatePolicy) -> bool {
+        let mut live = self.live().lock().unwrap();
+        if let Some(old) = live.get(imei) {
+            let action = match policy {
+                DuplicatePolicy::RejectNew => "rejected_new",
+                DuplicatePolicy::CloseOld => "closed_old",
+                DuplicatePolicy::Allow => "allowed",
+            };
+            metrics::counter!("duplicate_sessions_total", "action" => action).increment(1);
+            warn!("IMEI {} connected from {} while its session from {} is still open ({})", imei, session.peer, old.peer, action);
+            match policy {
+                DuplicatePolicy::RejectNew => return false,
+                DuplicatePolicy::CloseOld => old.close.cancel(),
+                DuplicatePolicy::Allow => {}
+            }
+        }
+        live.insert(imei.to_string(), LiveSession { id: session.id, peer: session.peer, close });
+        true
+    }
+
+    /// Drop the entry, unless a newer session already took it over.
+    pub fn unregister(&self, session: &Session) {
+        let mut live = self.live().lock().unwrap();
+        if live.get(session.imei()).is_some_and(|s| s.id == session.id) {
+            live.remove(session.imei());
+        }
+    }
+
+    /// Devices with a live session.
+    pub fn len(&self) -> usize {
+        self.live().lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_transitions() {
+        let mut session = Session::new("127.0.0.1:5000".parse().unwrap());
+        assert_eq!(session.state(), SessionState::AwaitingImei);
+        assert_eq!(session.expect_data(), Err(ProtocolError::DataBeforeImei));
+        assert_eq!(session.expect_imei(), Ok(()));
+
+        session.authenticate("356307042441013".to_string());
+        assert_eq!(session.state(), SessionState::Authenticated);
+        assert_eq!(session.imei(), "356307042441013");
+        assert_eq!(session.expect_data(), Ok(()));
+        assert_eq!(session.expect_imei(), Err(ProtocolError::SecondImei));
+
+        session.close("eof");
+        assert_eq!(session.state(), SessionState::Closing);
+    }
+
+    #[test]
+    fn test_duplicate_sessions() {
+        let registry = SessionRegistry::new();
+        let imei = "356307042441013";
+        let authenticated = |port: u16| {
+            let mut session = Session::new(SocketAddr::from(([127, 0, 0, 1], port)));
+            session.authenticate(imei.to_string());
+            session
+        };
+
+        let old = authenticated(5000);
+        let old_close = CancellationToken::new();
+        assert!(registry.register(imei, &old, old_close.clone(), DuplicatePolicy::CloseOld));
+
+        let new = authenticated(5001);
+        assert!(!registry.register(imei, &new, CancellationToken::new(), DuplicatePolicy::RejectNew));
+        assert!(!old_close.is_cancelled());
+
+        assert!(registry.register(imei, &new, CancellationToken::new(), DuplicatePolicy::CloseOld));
+        assert!(old_close.is_cancelled());
+
+        // The old session closing afterwards must not drop the live one
+        registry.unregister(&old);
+        assert_eq!(registry.len(), 1);
+        registry.unregister(&new);
+        assert_eq!(registry.len(), 0);
+    }
+}
@@ -0,0 +1,245 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use super::{DeviceStatus, DeviceStore, GeofenceState, NewWebhookJob, SessionSummary, SinkError, TelemetrySink, WebhookJob, WebhookStore};
+use crate::parser::models::AvlRecord;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct StoredBatch {
+    pub imei: String,
+    pub records: Vec<AvlRecord>,
+    pub raw: String,
+    pub status: String,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct StoredSession {
+    pub imei: String,
+    pub peer: String,
+    pub connected_at: DateTime<Utc>,
+    pub summary: Option<SessionSummary>,
+}
+
+#[derive(Debug, Clone)]
+struct QueuedJob {
+    job: WebhookJob,
+    next_attempt_at: DateTime<Utc>,
+}
+
+/// Row of the latest-state table: the record and when it was received.
+#[derive(Debug, Clone)]
+struct DeviceRow {
+    record: AvlRecord,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct WebhookTables {
+    next_id: i64,
+    queue: BTreeMap<i64, QueuedJob>,
+    dead_letter: Vec<(WebhookJob, String)>,
+}
+
+/// Keeps everything in process memory. Nothing survives a restart.
+#[derive(Default)]
+pub struct MemorySink {
+    batches: Mutex<Vec<StoredBatch>>,
+    states: Mutex<HashMap<String, DeviceRow>>,
+    geofences: Mutex<HashMap<(String, String), GeofenceState>>,
+    webhooks: Mutex<WebhookTables>,
+    devices: Mutex<HashMap<String, DeviceStatus>>,
+    sessions: Mutex<Vec<StoredSession>>,
+    /// Simulated outage: telemetry writes fail like a Postgres pool timeout.
+    unavailable: AtomicBool,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_available(&self) -> Result<(), SinkError> {
+        match self.unavailable.load(Ordering::Relaxed) {
+            true => Err(SinkError::Connect(sqlx::Error::PoolTimedOut)),
+            false => Ok(()),
+        }
+    }
+}
+
+// Inspection helpers, only used by tests for now.
+#[cfg_attr(not(test), allow(dead_code))]
+impl MemorySink {
+    pub fn batches(&self) -> Vec<StoredBatch> {
+        self.batches.lock().unwrap().clone()
+    }
+
+    pub fn state(&self, imei: &str) -> Option<AvlRecord> {
+        self.states.lock().unwrap().get(imei).map(|row| row.record.clone())
+    }
+
+    pub fn sessions(&self) -> Vec<StoredSession> {
+        self.sessions.lock().unwrap().clone()
+    }
+
+    /// Queued webhook jobs with their next attempt time.
+    pub fn queued_jobs(&self) -> Vec<(WebhookJob, DateTime<Utc>)> {
+        self.webhooks.lock().unwrap().queue.values().map(|q| (q.job.clone(), q.next_attempt_at)).collect()
+    }
+
+    /// Dead-lettered jobs with their last error.
+    pub fn dead_letters(&self) -> Vec<(WebhookJob, String)> {
+        self.webhooks.lock().unwrap().dead_letter.clone()
+    }
+
+    /// Make every queued job due now, instead of waiting out the backoff.
+    pub fn expire_backoffs(&self) {
+        for q in self.webhooks.lock().unwrap().queue.values_mut() {
+            q.next_attempt_at = Utc::now();
+        }
+    }
+
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.unavailable.store(unavailable, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl TelemetrySink for MemorySink {
+    async fn save_batch(&self, imei: &str, records: &[AvlRecord], raw: &str, status: &str) -> Result<Option<i64>, SinkError> {
+        self.check_available()?;
+        let mut batches = self.batches.lock().unwrap();
+        batches.push(StoredBatch {
+            imei: imei.to_string(),
+            records: records.to_vec(),
+            raw: raw.to_string(),
+            status: status.to_string(),
+        });
+        Ok(Some(batches.len() as i64))
+    }
+
+    async fn check_health(&self) -> Result<(), SinkError> {
+        self.check_available()
+    }
+
+    async fn upsert_state(&self, imei: &str, record: &AvlRecord) -> Result<(), SinkError> {
+        self.check_available()?;
+        let mut states = self.states.lock().unwrap();
+        // Same guard as the SQL upsert: an older record never replaces a newer one
+        if states.get(imei).is_some_and(|row| row.record.timestamp > record.timestamp) {
+            return Ok(());
+        }
+        states.insert(imei.to_string(), DeviceRow { record: record.clone(), updated_at: Utc::now() });
+        Ok(())
+    }
+
+    async fn last_seen(&self) -> Result<Vec<(String, DateTime<Utc>)>, SinkError> {
+        self.check_available()?;
+        Ok(self.states.lock().unwrap().iter().map(|(imei, row)| (imei.clone(), row.updated_at)).collect())
+    }
+
+    async fn upsert_geofence_state(&self, state: &GeofenceState) -> Result<(), SinkError> {
+        self.check_available()?;
+        let mut geofences = self.geofences.lock().unwrap();
+        let key = (state.imei.clone(), state.geofence.clone());
+        if geofences.get(&key).is_some_and(|stored| stored.recorded_at > state.recorded_at) {
+            return Ok(());
+        }
+        geofences.insert(key, state.clone());
+        Ok(())
+    }
+
+    async fn geofence_states(&self) -> Result<Vec<GeofenceState>, SinkError> {
+        self.check_available()?;
+        Ok(self.geofences.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn open_session(&self, imei: &str, peer: &str, connected_at: DateTime<Utc>) -> Result<Option<i64>, SinkError> {
+        self.check_available()?;
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.push(StoredSession { imei: imei.to_string(), peer: peer.to_string(), connected_at, summary: None });
+        Ok(Some(sessions.len() as i64))
+    }
+
+    async fn close_session(&self, id: i64, summary: &SessionSummary) -> Result<(), SinkError> {
+        self.check_available()?;
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(id as usize - 1) {
+            session.summary = Some(summary.clone());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WebhookStore for MemorySink {
+    async fn enqueue(&self, job: &NewWebhookJob) -> Result<i64, SinkError> {
+        let mut tables = self.webhooks.lock().unwrap();
+        tables.next_id += 1;
+        let id = tables.next_id;
+        let now = Utc::now();
+        tables.queue.insert(id, QueuedJob {
+            job: WebhookJob {
+                id,
+                subscriber: job.subscriber.clone(),
+                body: job.body.clone(),
+                attempts: 0,
+                created_at: now,
+            },
+            next_attempt_at: now,
+        });
+        Ok(id)
+    }
+
+    async fn due_jobs(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<WebhookJob>, SinkError> {
+        let tables = self.webhooks.lock().unwrap();
+        let mut due: Vec<&QueuedJob> = tables.queue.values().filter(|q| q.next_attempt_at <= now).collect();
+        due.sort_by_key(|q| (q.next_attempt_at, q.job.id));
+        Ok(due.into_iter().take(limit.max(0) as usize).map(|q| q.job.clone()).collect())
+    }
+
+    async fn mark_delivered(&self, id: i64) -> Result<(), SinkError> {
+        self.webhooks.lock().unwrap().queue.remove(&id);
+        Ok(())
+    }
+
+    async fn reschedule(&self, id: i64, attempts: i32, next_attempt_at: DateTime<Utc>, _last_error: &str) -> Result<(), SinkError> {
+        if let Some(q) = self.webhooks.lock().unwrap().queue.get_mut(&id) {
+            q.job.attempts = attempts;
+            q.next_attempt_at = next_attempt_at;
+        }
+        Ok(())
+    }
+
+    async fn dead_letter(&self, id: i64, attempts: i32, last_error: &str) -> Result<(), SinkError> {
+        let mut tables = self.webhooks.lock().unwrap();
+        if let Some(mut q) = tables.queue.remove(&id) {
+            q.job.attempts = attempts;
+            tables.dead_letter.push((q.job, last_error.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn pending_count(&self) -> Result<i64, SinkError> {
+        Ok(self.webhooks.lock().unwrap().queue.len() as i64)
+    }
+}
+
+#[async_trait]
+impl DeviceStore for MemorySink {
+    async fn device_status(&self, imei: &str) -> Result<Option<DeviceStatus>, SinkError> {
+        Ok(self.devices.lock().unwrap().get(imei).copied())
+    }
+
+    async fn enroll(&self, imei: &str, status: DeviceStatus) -> Result<bool, SinkError> {
+        let mut devices = self.devices.lock().unwrap();
+        if devices.contains_key(imei) {
+            return Ok(false);
+        }
+        devices.insert(imei.to_string(), status);
+        Ok(true)
+    }
+}
@@ -0,0 +1,211 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::info;
+
+use crate::config::Settings;
+use crate::tunnel::{SshTunnel, TunnelError};
+use crate::parser::models::AvlRecord;
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+pub use memory::MemorySink;
+pub use postgres::PostgresSink;
+pub use sqlite::SqliteSink;
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error("query failed: {source}")]
+    Query {
+        sql: &'static str,
+        #[source]
+        source: sqlx::Error,
+    },
+    #[error("database connection failed: {0}")]
+    Connect(#[from] sqlx::Error),
+    #[error("ssh tunnel failed: {0}")]
+    Tunnel(#[from] TunnelError),
+    #[error("unknown storage backend: {0}")]
+    UnknownBackend(String),
+}
+
+impl SinkError {
+    pub fn query(sql: &'static str, source: sqlx::Error) -> Self {
+        SinkError::Query { sql, source }
+    }
+
+    /// SQL statement that failed, if any (used for the Teams SQL error card).
+    pub fn sql(&self) -> &str {
+        match self {
+            SinkError::Query { sql, .. } => sql,
+            _ => "",
+        }
+    }
+}
+
+/// Destination for decoded telemetry.
+///
+/// `handle_client` only talks to this trait, so the server can run against
+/// Postgres in production, SQLite on small edge boxes, or memory in tests.
+#[async_trait]
+pub trait TelemetrySink: Send + Sync {
+    /// Store one AVL packet (all its records plus the raw hex) as a batch.
+    /// Returns the id of the stored row when the backend has one.
+    async fn save_batch(&self, imei: &str, records: &[AvlRecord], raw: &str, status: &str) -> Result<Option<i64>, SinkError>;
+
+    /// Cheap round-trip used by the `/health` endpoint.
+    async fn check_health(&self) -> Result<(), SinkError>;
+
+    /// Keep the latest known record per device.
+    async fn upsert_state(&self, imei: &str, record: &AvlRecord) -> Result<(), SinkError>;
+
+    /// When each device in the latest-state table was last updated.
+    async fn last_seen(&self) -> Result<Vec<(String, DateTime<Utc>)>, SinkError>;
+
+    /// Keep the inside/outside state per device and geofence. Like
+    /// `upsert_state`, an older fix never replaces a newer one.
+    async fn upsert_geofence_state(&self, state: &GeofenceState) -> Result<(), SinkError>;
+
+    /// Every stored geofence state, to seed the engine at startup.
+    async fn geofence_states(&self) -> Result<Vec<GeofenceState>, SinkError>;
+
+    /// Record an authenticated connection in `device_sessions`. Returns the row id.
+    async fn open_session(&self, imei: &str, peer: &str, connected_at: DateTime<Utc>) -> Result<Option<i64>, SinkError>;
+
+    /// Fill in the disconnect of a row created by `open_session`.
+    async fn close_session(&self, id: i64, summary: &SessionSummary) -> Result<(), SinkError>;
+
+    /// Wait for in-flight writes and close the connections (graceful shutdown).
+    async fn close(&self) {}
+}
+
+/// Traffic and end of one device connection, as known by `handle_client`.
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub disconnected_at: DateTime<Utc>,
+    /// "eof", "timeout", "parse_error", "shutdown", ...
+    pub reason: String,
+    pub packets: i64,
+    pub records: i64,
+    pub bytes_received: i64,
+    pub bytes_sent: i64,
+    pub duration_ms: i64,
+}
+
+/// Row of `teltonika_geofence_state`: whether a device was inside a zone at its last checked fix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeofenceState {
+    pub imei: String,
+    pub geofence: String,
+    pub inside: bool,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewWebhookJob {
+    pub subscriber: String,
+    pub url: String,
+    /// Serialized JSON body, stored as-is so retries send identical bytes.
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookJob {
+    pub id: i64,
+    /// Delivery target, looked up in the current config (the stored `url` is only a record).
+    pub subscriber: String,
+    pub body: String,
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Durable queue behind the background webhook worker.
+///
+/// Jobs stay in `webhook_queue` until delivered; once `webhook.max_attempts`
+/// is reached they are moved to `webhook_dead_letter` for manual replay.
+#[async_trait]
+pub trait WebhookStore: Send + Sync {
+    async fn enqueue(&self, job: &NewWebhookJob) -> Result<i64, SinkError>;
+
+    /// Jobs whose `next_attempt_at` has passed, oldest first.
+    async fn due_jobs(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<WebhookJob>, SinkError>;
+
+    async fn mark_delivered(&self, id: i64) -> Result<(), SinkError>;
+
+    async fn reschedule(&self, id: i64, attempts: i32, next_attempt_at: DateTime<Utc>, last_error: &str) -> Result<(), SinkError>;
+
+    async fn dead_letter(&self, id: i64, attempts: i32, last_error: &str) -> Result<(), SinkError>;
+
+    async fn pending_count(&self) -> Result<i64, SinkError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceStatus {
+    Active,
+    /// Auto-enrolled unknown device, rejected until an operator activates it.
+    Quarantine,
+    Blocked,
+}
+
+impl DeviceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceStatus::Active => "active",
+            DeviceStatus::Quarantine => "quarantine",
+            DeviceStatus::Blocked => "blocked",
+        }
+    }
+
+    /// Anything unexpected in the table counts as blocked.
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "active" => DeviceStatus::Active,
+            "quarantine" => DeviceStatus::Quarantine,
+            _ => DeviceStatus::Blocked,
+        }
+    }
+}
+
+/// Registered devices (`teltonika_devices`), checked at the handshake.
+#[async_trait]
+pub trait DeviceStore: Send + Sync {
+    async fn device_status(&self, imei: &str) -> Result<Option<DeviceStatus>, SinkError>;
+
+    /// Register `imei` unless it already is. Returns whether a row was added.
+    async fn enroll(&self, imei: &str, status: DeviceStatus) -> Result<bool, SinkError>;
+}
+
+/// Everything the selected backend provides.
+pub struct Storage {
+    pub telemetry: Arc<dyn TelemetrySink>,
+    pub webhooks: Arc<dyn WebhookStore>,
+    pub devices: Arc<dyn DeviceStore>,
+    /// SSH tunnel (Postgres only); must be kept alive as long as the pool is used.
+    pub tunnel: Option<SshTunnel>,
+}
+
+/// Build the backend selected by `storage.backend`.
+pub async fn from_settings(settings: &Settings) -> Result<Storage, SinkError> {
+    let backend = settings.storage.backend.to_lowercase();
+    info!("Using '{}' storage backend", backend);
+
+    match backend.as_str() {
+        "postgres" | "postgresql" => {
+            let (sink, tunnel) = PostgresSink::connect().await?;
+            let sink = Arc::new(sink);
+            Ok(Storage { telemetry: sink.clone(), webhooks: sink.clone(), devices: sink, tunnel })
+        }
+        "sqlite" => {
+            let sink = Arc::new(SqliteSink::connect(&settings.storage.sqlite_path).await?);
+            Ok(Storage { telemetry: sink.clone(), webhooks: sink.clone(), devices: sink, tunnel: None })
+        }
+        "memory" => {
+            let sink = Arc::new(MemorySink::new());
+            Ok(Storage { telemetry: sink.clone(), webhooks: sink.clone(), devices: sink, tunnel: None })
+        }
+        other => Err(SinkError::UnknownBackend(other.to_string())),
+    }
+}
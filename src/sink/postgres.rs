@@ -0,0 +1,195 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::{DeviceStatus, DeviceStore, GeofenceState, NewWebhookJob, SessionSummary, SinkError, TelemetrySink, WebhookJob, WebhookStore};
+use crate::config::get_settings;
+use crate::db::{init_db, DeviceRepo, DeviceSessionRepo, TeltonikaDataRepo, WebhookQueueRepo};
+use crate::notifications::{NotificationService, Severity};
+use crate::parser::models::AvlRecord;
+use crate::tunnel::SshTunnel;
+use crate::utils::backoff_delay;
+
+/// How long startup waits for the SSH forward before carrying on without it.
+const TUNNEL_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Production backend: `teltonika_data` rows in Postgres, optionally behind an SSH tunnel.
+pub struct PostgresSink {
+    pool: PgPool,
+    /// Whether `teltonika_data` has an `id` column to return, known once the schema is checked.
+    has_row_id: Arc<AtomicBool>,
+}
+
+impl PostgresSink {
+    /// Never fails because Postgres is down: the pool connects lazily and the
+    /// tables are created in the background, retrying with backoff, so the
+    /// device listener starts regardless. Only local errors (e.g. the tunnel
+    /// port already in use) are returned.
+    pub async fn connect() -> Result<(Self, Option<SshTunnel>), SinkError> {
+        let settings = get_settings();
+        let tunnel = if settings.ssh.enabled() {
+            let tunnel = SshTunnel::start(&settings.ssh, settings.database.port)?;
+            if let Err(e) = tunnel.wait_up(TUNNEL_STARTUP_TIMEOUT).await {
+                warn!("Starting without the database: {}", e);
+            }
+            Some(tunnel)
+        } else {
+            None
+        };
+
+        let pool = init_db();
+        let has_row_id = Arc::new(AtomicBool::new(false));
+        tokio::spawn(prepare_schema(pool.clone(), has_row_id.clone()));
+        Ok((PostgresSink { pool, has_row_id }, tunnel))
+    }
+}
+
+async fn ensure_schema(pool: &PgPool, has_row_id: &AtomicBool) -> Result<(), SinkError> {
+    let has_id = TeltonikaDataRepo::has_id_column(pool).await
+        .map_err(|e| SinkError::query(TeltonikaDataRepo::HAS_ID_COLUMN_SQL, e))?;
+    if !has_id {
+        warn!("teltonika_data has no id column: webhooks will carry row_id null");
+    }
+    has_row_id.store(has_id, Ordering::Relaxed);
+
+    // One statement at a time, so a failure reports the statement that failed
+    for sql in [
+        TeltonikaDataRepo::CREATE_STATE_SQL,
+        TeltonikaDataRepo::CREATE_GEOFENCE_STATE_SQL,
+        WebhookQueueRepo::CREATE_QUEUE_SQL,
+        WebhookQueueRepo::CREATE_DEAD_LETTER_SQL,
+        DeviceRepo::CREATE_DEVICES_SQL,
+        DeviceSessionRepo::CREATE_SESSIONS_SQL,
+        DeviceSessionRepo::CREATE_SESSIONS_INDEX_SQL,
+    ] {
+        sqlx::query(sql).execute(pool).await.map_err(|e| SinkError::query(sql, e))?;
+    }
+    Ok(())
+}
+
+/// First connection: retry until the tables exist, alerting once while it fails.
+async fn prepare_schema(pool: PgPool, has_row_id: Arc<AtomicBool>) {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match ensure_schema(&pool, &has_row_id).await {
+            Ok(()) => {
+                info!("Database ready");
+                if attempts > 1 {
+                    NotificationService::notify(Severity::Info, "✅ Database connected", &format!("After {} attempts", attempts));
+                }
+                return;
+            }
+            Err(e) => {
+                let delay = backoff_delay(attempts, 1000, get_settings().database.connect_backoff_max_ms);
+                warn!("Database not reachable (attempt {}), retrying in {:?}: {}", attempts, delay, e);
+                if attempts == 1 {
+                    NotificationService::notify(Severity::Error, "❌ Database unreachable", &format!("{}<br/>Devices are still accepted; retrying in the background.", e));
+                }
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TelemetrySink for PostgresSink {
+    async fn save_batch(&self, imei: &str, records: &[AvlRecord], raw: &str, status: &str) -> Result<Option<i64>, SinkError> {
+        let returning_id = self.has_row_id.load(Ordering::Relaxed);
+        TeltonikaDataRepo::save_avl_data(&self.pool, imei, records, raw, status, returning_id).await
+            .map_err(|e| match returning_id {
+                true => SinkError::query(TeltonikaDataRepo::INSERT_DATA_SQL, e),
+                false => SinkError::query(TeltonikaDataRepo::INSERT_DATA_NO_ID_SQL, e),
+            })
+    }
+
+    async fn check_health(&self) -> Result<(), SinkError> {
+        TeltonikaDataRepo::check_health(&self.pool).await
+            .map_err(|e| SinkError::query("SELECT 1", e))
+    }
+
+    async fn upsert_state(&self, imei: &str, record: &AvlRecord) -> Result<(), SinkError> {
+        TeltonikaDataRepo::upsert_device_state(&self.pool, imei, record).await
+            .map_err(|e| SinkError::query(TeltonikaDataRepo::UPSERT_STATE_SQL, e))
+    }
+
+    async fn last_seen(&self) -> Result<Vec<(String, DateTime<Utc>)>, SinkError> {
+        TeltonikaDataRepo::last_seen(&self.pool).await
+            .map_err(|e| SinkError::query(TeltonikaDataRepo::LAST_SEEN_SQL, e))
+    }
+
+    async fn upsert_geofence_state(&self, state: &GeofenceState) -> Result<(), SinkError> {
+        TeltonikaDataRepo::upsert_geofence_state(&self.pool, state).await
+            .map_err(|e| SinkError::query(TeltonikaDataRepo::UPSERT_GEOFENCE_STATE_SQL, e))
+    }
+
+    async fn geofence_states(&self) -> Result<Vec<GeofenceState>, SinkError> {
+        TeltonikaDataRepo::geofence_states(&self.pool).await
+            .map_err(|e| SinkError::query(TeltonikaDataRepo::GEOFENCE_STATES_SQL, e))
+    }
+
+    async fn open_session(&self, imei: &str, peer: &str, connected_at: DateTime<Utc>) -> Result<Option<i64>, SinkError> {
+        DeviceSessionRepo::open(&self.pool, imei, peer, connected_at).await
+            .map(Some)
+            .map_err(|e| SinkError::query(DeviceSessionRepo::OPEN_SQL, e))
+    }
+
+    async fn close_session(&self, id: i64, summary: &SessionSummary) -> Result<(), SinkError> {
+        DeviceSessionRepo::close(&self.pool, id, summary).await
+            .map_err(|e| SinkError::query(DeviceSessionRepo::CLOSE_SQL, e))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait]
+impl WebhookStore for PostgresSink {
+    async fn enqueue(&self, job: &NewWebhookJob) -> Result<i64, SinkError> {
+        WebhookQueueRepo::enqueue(&self.pool, job).await
+            .map_err(|e| SinkError::query(WebhookQueueRepo::ENQUEUE_SQL, e))
+    }
+
+    async fn due_jobs(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<WebhookJob>, SinkError> {
+        WebhookQueueRepo::due(&self.pool, now, limit).await
+            .map_err(|e| SinkError::query(WebhookQueueRepo::DUE_SQL, e))
+    }
+
+    async fn mark_delivered(&self, id: i64) -> Result<(), SinkError> {
+        WebhookQueueRepo::delete(&self.pool, id).await
+            .map_err(|e| SinkError::query(WebhookQueueRepo::DELETE_SQL, e))
+    }
+
+    async fn reschedule(&self, id: i64, attempts: i32, next_attempt_at: DateTime<Utc>, last_error: &str) -> Result<(), SinkError> {
+        WebhookQueueRepo::reschedule(&self.pool, id, attempts, next_attempt_at, last_error).await
+            .map_err(|e| SinkError::query(WebhookQueueRepo::RESCHEDULE_SQL, e))
+    }
+
+    async fn dead_letter(&self, id: i64, attempts: i32, last_error: &str) -> Result<(), SinkError> {
+        WebhookQueueRepo::dead_letter(&self.pool, id, attempts, last_error).await
+            .map_err(|e| SinkError::query(WebhookQueueRepo::DEAD_LETTER_SQL, e))
+    }
+
+    async fn pending_count(&self) -> Result<i64, SinkError> {
+        WebhookQueueRepo::count(&self.pool).await
+            .map_err(|e| SinkError::query(WebhookQueueRepo::COUNT_SQL, e))
+    }
+}
+
+#[async_trait]
+impl DeviceStore for PostgresSink {
+    async fn device_status(&self, imei: &str) -> Result<Option<DeviceStatus>, SinkError> {
+        DeviceRepo::status(&self.pool, imei).await
+            .map_err(|e| SinkError::query(DeviceRepo::STATUS_SQL, e))
+    }
+
+    async fn enroll(&self, imei: &str, status: DeviceStatus) -> Result<bool, SinkError> {
+        DeviceRepo::enroll(&self.pool, imei, status).await
+            .map_err(|e| SinkError::query(DeviceRepo::ENROLL_SQL, e))
+    }
+}
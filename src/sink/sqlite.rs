@@ -0,0 +1,459 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::str::FromStr;
+use tracing::info;
+
+use super::{DeviceStatus, DeviceStore, GeofenceState, NewWebhookJob, SessionSummary, SinkError, TelemetrySink, WebhookJob, WebhookStore};
+use crate::parser::models::AvlRecord;
+
+const CREATE_DATA_SQL: &str = "CREATE TABLE IF NOT EXISTS teltonika_data (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    imei TEXT NOT NULL,
+    data TEXT NOT NULL,
+    raw TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    status TEXT NOT NULL
+)";
+
+const CREATE_STATE_SQL: &str = "CREATE TABLE IF NOT EXISTS teltonika_device_state (
+    imei TEXT PRIMARY KEY,
+    data TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+)";
+
+const CREATE_GEOFENCE_STATE_SQL: &str = "CREATE TABLE IF NOT EXISTS teltonika_geofence_state (
+    imei TEXT NOT NULL,
+    geofence TEXT NOT NULL,
+    inside INTEGER NOT NULL,
+    recorded_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (imei, geofence)
+)";
+
+const CREATE_QUEUE_SQL: &str = "CREATE TABLE IF NOT EXISTS webhook_queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscriber TEXT NOT NULL,
+    url TEXT NOT NULL,
+    body TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TEXT NOT NULL,
+    last_error TEXT,
+    created_at TEXT NOT NULL
+)";
+
+const CREATE_DEAD_LETTER_SQL: &str = "CREATE TABLE IF NOT EXISTS webhook_dead_letter (
+    id INTEGER PRIMARY KEY,
+    subscriber TEXT NOT NULL,
+    url TEXT NOT NULL,
+    body TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_error TEXT,
+    created_at TEXT NOT NULL,
+    failed_at TEXT NOT NULL
+)";
+
+const CREATE_DEVICES_SQL: &str = "CREATE TABLE IF NOT EXISTS teltonika_devices (
+    imei TEXT PRIMARY KEY,
+    status TEXT NOT NULL,
+    label TEXT,
+    created_at TEXT NOT NULL
+)";
+
+const CREATE_SESSIONS_SQL: &str = "CREATE TABLE IF NOT EXISTS device_sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    imei TEXT NOT NULL,
+    peer_addr TEXT NOT NULL,
+    connected_at TEXT NOT NULL,
+    disconnected_at TEXT,
+    disconnect_reason TEXT,
+    packets INTEGER NOT NULL DEFAULT 0,
+    records INTEGER NOT NULL DEFAULT 0,
+    bytes_received INTEGER NOT NULL DEFAULT 0,
+    bytes_sent INTEGER NOT NULL DEFAULT 0,
+    duration_ms INTEGER
+)";
+
+const CREATE_SESSIONS_INDEX_SQL: &str = "CREATE INDEX IF NOT EXISTS device_sessions_imei_idx ON device_sessions (imei, connected_at DESC)";
+
+const INSERT_DATA_SQL: &str = "INSERT INTO teltonika_data (imei, data, raw, created_at, status) VALUES (?, ?, ?, ?, ?)";
+
+const UPSERT_STATE_SQL: &str = "INSERT INTO teltonika_device_state (imei, data, recorded_at, updated_at) VALUES (?, ?, ?, ?)
+    ON CONFLICT (imei) DO UPDATE SET data = excluded.data, recorded_at = excluded.recorded_at, updated_at = excluded.updated_at
+    WHERE teltonika_device_state.recorded_at <= excluded.recorded_at";
+
+const LAST_SEEN_SQL: &str = "SELECT imei, updated_at FROM teltonika_device_state";
+
+const UPSERT_GEOFENCE_STATE_SQL: &str = "INSERT INTO teltonika_geofence_state (imei, geofence, inside, recorded_at, updated_at) VALUES (?, ?, ?, ?, ?)
+    ON CONFLICT (imei, geofence) DO UPDATE SET inside = excluded.inside, recorded_at = excluded.recorded_at, updated_at = excluded.updated_at
+    WHERE teltonika_geofence_state.recorded_at <= excluded.recorded_at";
+
+const GEOFENCE_STATES_SQL: &str = "SELECT imei, geofence, inside, recorded_at FROM teltonika_geofence_state";
+
+const ENQUEUE_SQL: &str = "INSERT INTO webhook_queue (subscriber, url, body, attempts, next_attempt_at, created_at) VALUES (?, ?, ?, 0, ?, ?)";
+
+const DUE_SQL: &str = "SELECT id, subscriber, body, attempts, created_at FROM webhook_queue WHERE next_attempt_at <= ? ORDER BY next_attempt_at, id LIMIT ?";
+
+const DELETE_SQL: &str = "DELETE FROM webhook_queue WHERE id = ?";
+
+const RESCHEDULE_SQL: &str = "UPDATE webhook_queue SET attempts = ?, next_attempt_at = ?, last_error = ? WHERE id = ?";
+
+const DEAD_LETTER_SQL: &str = "INSERT INTO webhook_dead_letter (id, subscriber, url, body, attempts, last_error, created_at, failed_at)
+    SELECT id, subscriber, url, body, ?, ?, created_at, ? FROM webhook_queue WHERE id = ?";
+
+const COUNT_SQL: &str = "SELECT COUNT(*) FROM webhook_queue";
+
+const OPEN_SESSION_SQL: &str = "INSERT INTO device_sessions (imei, peer_addr, connected_at) VALUES (?, ?, ?)";
+
+const CLOSE_SESSION_SQL: &str = "UPDATE device_sessions SET disconnected_at = ?, disconnect_reason = ?, packets = ?, records = ?,
+    bytes_received = ?, bytes_sent = ?, duration_ms = ? WHERE id = ?";
+
+const DEVICE_STATUS_SQL: &str = "SELECT status FROM teltonika_devices WHERE imei = ?";
+
+const ENROLL_SQL: &str = "INSERT INTO teltonika_devices (imei, status, created_at) VALUES (?, ?, ?) ON CONFLICT (imei) DO NOTHING";
+
+/// Single-file backend for edge boxes without Postgres. Tables are created on startup.
+pub struct SqliteSink {
+    pool: SqlitePool,
+}
+
+impl SqliteSink {
+    pub async fn connect(path: &str) -> Result<Self, SinkError> {
+        info!("Opening SQLite database at {}...", path);
+
+        let options = SqliteConnectOptions::from_str(path)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options).await?;
+
+        for sql in [CREATE_DATA_SQL, CREATE_STATE_SQL, CREATE_GEOFENCE_STATE_SQL, CREATE_QUEUE_SQL, CREATE_DEAD_LETTER_SQL, CREATE_DEVICES_SQL, CREATE_SESSIONS_SQL, CREATE_SESSIONS_INDEX_SQL] {
+            sqlx::query(sql).execute(&pool).await.map_err(|e| SinkError::query(sql, e))?;
+        }
+
+        Ok(SqliteSink { pool })
+    }
+}
+
+#[async_trait]
+impl TelemetrySink for SqliteSink {
+    async fn save_batch(&self, imei: &str, records: &[AvlRecord], raw: &str, status: &str) -> Result<Option<i64>, SinkError> {
+        sqlx::query(INSERT_DATA_SQL)
+            .bind(imei)
+            .bind(json!(records))
+            .bind(raw)
+            .bind(chrono::Utc::now())
+            .bind(status)
+            .execute(&self.pool).await
+            .map(|res| Some(res.last_insert_rowid()))
+            .map_err(|e| SinkError::query(INSERT_DATA_SQL, e))
+    }
+
+    async fn check_health(&self) -> Result<(), SinkError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await
+            .map(|_| ())
+            .map_err(|e| SinkError::query("SELECT 1", e))
+    }
+
+    async fn upsert_state(&self, imei: &str, record: &AvlRecord) -> Result<(), SinkError> {
+        sqlx::query(UPSERT_STATE_SQL)
+            .bind(imei)
+            .bind(json!(record))
+            .bind(record.timestamp)
+            .bind(chrono::Utc::now())
+            .execute(&self.pool).await
+            .map(|_| ())
+            .map_err(|e| SinkError::query(UPSERT_STATE_SQL, e))
+    }
+
+    async fn last_seen(&self) -> Result<Vec<(String, DateTime<Utc>)>, SinkError> {
+        sqlx::query_as::<_, (String, DateTime<Utc>)>(LAST_SEEN_SQL)
+            .fetch_all(&self.pool).await
+            .map_err(|e| SinkError::query(LAST_SEEN_SQL, e))
+    }
+
+    async fn upsert_geofence_state(&self, state: &GeofenceState) -> Result<(), SinkError> {
+        sqlx::query(UPSERT_GEOFENCE_STATE_SQL)
+            .bind(&state.imei)
+            .bind(&state.geofence)
+            .bind(state.inside)
+            .bind(state.recorded_at)
+            .bind(chrono::Utc::now())
+            .execute(&self.pool).await
+            .map(|_| ())
+            .map_err(|e| SinkError::query(UPSERT_GEOFENCE_STATE_SQL, e))
+    }
+
+    async fn geofence_states(&self) -> Result<Vec<GeofenceState>, SinkError> {
+        sqlx::query_as::<_, (String, String, bool, DateTime<Utc>)>(GEOFENCE_STATES_SQL)
+            .fetch_all(&self.pool).await
+            .map(|rows| rows.into_iter().map(|(imei, geofence, inside, recorded_at)| GeofenceState { imei, geofence, inside, recorded_at }).collect())
+            .map_err(|e| SinkError::query(GEOFENCE_STATES_SQL, e))
+    }
+
+    async fn open_session(&self, imei: &str, peer: &str, connected_at: DateTime<Utc>) -> Result<Option<i64>, SinkError> {
+        sqlx::query(OPEN_SESSION_SQL)
+            .bind(imei)
+            .bind(peer)
+            .bind(connected_at)
+            .execute(&self.pool).await
+            .map(|res| Some(res.last_insert_rowid()))
+            .map_err(|e| SinkError::query(OPEN_SESSION_SQL, e))
+    }
+
+    async fn close_session(&self, id: i64, summary: &SessionSummary) -> Result<(), SinkError> {
+        sqlx::query(CLOSE_SESSION_SQL)
+            .bind(summary.disconnected_at)
+            .bind(&summary.reason)
+            .bind(summary.packets)
+            .bind(summary.records)
+            .bind(summary.bytes_received)
+            .bind(summary.bytes_sent)
+            .bind(summary.duration_ms)
+            .bind(id)
+            .execute(&self.pool).await
+            .map(|_| ())
+            .map_err(|e| SinkError::query(CLOSE_SESSION_SQL, e))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+#[async_trait]
+impl WebhookStore for SqliteSink {
+    async fn enqueue(&self, job: &NewWebhookJob) -> Result<i64, SinkError> {
+        let now = Utc::now();
+        sqlx::query(ENQUEUE_SQL)
+            .bind(&job.subscriber)
+            .bind(&job.url)
+            .bind(&job.body)
+            .bind(now)
+            .bind(now)
+            .execute(&self.pool).await
+            .map(|res| res.last_insert_rowid())
+            .map_err(|e| SinkError::query(ENQUEUE_SQL, e))
+    }
+
+    async fn due_jobs(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<WebhookJob>, SinkError> {
+        let rows = sqlx::query(DUE_SQL)
+            .bind(now)
+            .bind(limit)
+            .fetch_all(&self.pool).await
+            .map_err(|e| SinkError::query(DUE_SQL, e))?;
+
+        rows.iter().map(|row| Ok(WebhookJob {
+            id: row.try_get("id")?,
+            subscriber: row.try_get("subscriber")?,
+            body: row.try_get("body")?,
+            attempts: row.try_get("attempts")?,
+            created_at: row.try_get("created_at")?,
+        })).collect::<Result<_, sqlx::Error>>()
+            .map_err(|e| SinkError::query(DUE_SQL, e))
+    }
+
+    async fn mark_delivered(&self, id: i64) -> Result<(), SinkError> {
+        sqlx::query(DELETE_SQL).bind(id).execute(&self.pool).await
+            .map(|_| ())
+            .map_err(|e| SinkError::query(DELETE_SQL, e))
+    }
+
+    async fn reschedule(&self, id: i64, attempts: i32, next_attempt_at: DateTime<Utc>, last_error: &str) -> Result<(), SinkError> {
+        sqlx::query(RESCHEDULE_SQL)
+            .bind(attempts)
+            .bind(next_attempt_at)
+            .bind(last_error)
+            .bind(id)
+            .execute(&self.pool).await
+            .map(|_| ())
+            .map_err(|e| SinkError::query(RESCHEDULE_SQL, e))
+    }
+
+    async fn dead_letter(&self, id: i64, attempts: i32, last_error: &str) -> Result<(), SinkError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(DEAD_LETTER_SQL)
+            .bind(attempts)
+            .bind(last_error)
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&mut *tx).await
+            .map_err(|e| SinkError::query(DEAD_LETTER_SQL, e))?;
+        sqlx::query(DELETE_SQL).bind(id).execute(&mut *tx).await
+            .map_err(|e| SinkError::query(DELETE_SQL, e))?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn pending_count(&self) -> Result<i64, SinkError> {
+        sqlx::query_scalar::<_, i64>(COUNT_SQL).fetch_one(&self.pool).await
+            .map_err(|e| SinkError::query(COUNT_SQL, e))
+    }
+}
+
+#[async_trait]
+impl DeviceStore for SqliteSink {
+    async fn device_status(&self, imei: &str) -> Result<Option<DeviceStatus>, SinkError> {
+        sqlx::query_scalar::<_, String>(DEVICE_STATUS_SQL)
+            .bind(imei)
+            .fetch_optional(&self.pool).await
+            .map(|status| status.as_deref().map(DeviceStatus::parse))
+            .map_err(|e| SinkError::query(DEVICE_STATUS_SQL, e))
+    }
+
+    async fn enroll(&self, imei: &str, status: DeviceStatus) -> Result<bool, SinkError> {
+        sqlx::query(ENROLL_SQL)
+            .bind(imei)
+            .bind(status.as_str())
+            .bind(Utc::now())
+            .execute(&self.pool).await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(|e| SinkError::query(ENROLL_SQL, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::models::{IoGroup, TeltonikaGps};
+
+    async fn sink() -> SqliteSink {
+        SqliteSink::connect("sqlite::memory:").await.unwrap()
+    }
+
+    fn record(secs: i64, speed: i16) -> AvlRecord {
+        AvlRecord {
+            timestamp: DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+            priority: 0,
+            gps: TeltonikaGps { longitude: 5.374, latitude: 43.2951, altitude: 0, angle: 0, satellites: 8, speed },
+            event_id: 0,
+            io_groups: IoGroup { n1: vec![], n2: vec![], n4: vec![], n8: vec![], nx: vec![] },
+            io_elements: vec![],
+            properties_count: 0,
+        }
+    }
+
+    fn job(subscriber: &str) -> NewWebhookJob {
+        NewWebhookJob { subscriber: subscriber.to_string(), url: "https://api.example.com/hook".to_string(), body: "{}".to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_save_batch() {
+        let sink = sink().await;
+        assert_eq!(sink.save_batch("356307042441013", &[record(0, 4), record(10, 5)], "00ff", "new").await.unwrap(), Some(1));
+        assert_eq!(sink.save_batch("356307042441013", &[record(20, 6)], "01ff", "new").await.unwrap(), Some(2));
+        sink.check_health().await.unwrap();
+
+        let (imei, data, raw, status): (String, String, String, String) = sqlx::query_as("SELECT imei, data, raw, status FROM teltonika_data WHERE id = 1")
+            .fetch_one(&sink.pool).await.unwrap();
+        assert_eq!((imei.as_str(), raw.as_str(), status.as_str()), ("356307042441013", "00ff", "new"));
+        let data: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(data.as_array().unwrap().len(), 2);
+        assert_eq!(data[1]["gps"]["speed"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_state_keeps_the_newest_record() {
+        let sink = sink().await;
+        let before = Utc::now();
+        sink.upsert_state("356307042441013", &record(60, 4)).await.unwrap();
+        // Resent from the device's buffer after a newer one: ignored
+        sink.upsert_state("356307042441013", &record(0, 9)).await.unwrap();
+        sink.upsert_state("356307042441021", &record(0, 1)).await.unwrap();
+
+        let (data, recorded_at): (String, DateTime<Utc>) = sqlx::query_as("SELECT data, recorded_at FROM teltonika_device_state WHERE imei = '356307042441013'")
+            .fetch_one(&sink.pool).await.unwrap();
+        assert_eq!(recorded_at, record(60, 0).timestamp);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&data).unwrap()["gps"]["speed"], 4);
+
+        // Receive time, not GPS time
+        let mut last_seen = sink.last_seen().await.unwrap();
+        last_seen.sort();
+        assert_eq!(last_seen.iter().map(|(imei, _)| imei.as_str()).collect::<Vec<_>>(), ["356307042441013", "356307042441021"]);
+        assert!(last_seen.iter().all(|(_, at)| *at >= before));
+    }
+
+    #[tokio::test]
+    async fn test_geofence_states() {
+        let sink = sink().await;
+        let state = |geofence: &str, inside: bool, secs: i64| GeofenceState {
+            imei: "356307042441013".to_string(),
+            geofence: geofence.to_string(),
+            inside,
+            recorded_at: record(secs, 0).timestamp,
+        };
+        sink.upsert_geofence_state(&state("berth", true, 0)).await.unwrap();
+        sink.upsert_geofence_state(&state("berth", false, 60)).await.unwrap();
+        // Older fix, checked late: ignored
+        sink.upsert_geofence_state(&state("berth", true, 30)).await.unwrap();
+        sink.upsert_geofence_state(&state("harbour", true, 30)).await.unwrap();
+
+        let mut states = sink.geofence_states().await.unwrap();
+        states.sort_by(|a, b| a.geofence.cmp(&b.geofence));
+        assert_eq!(states, [state("berth", false, 60), state("harbour", true, 30)]);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_queue_round_trip() {
+        let sink = sink().await;
+        let first = sink.enqueue(&job("fleet")).await.unwrap();
+        let second = sink.enqueue(&job("crm")).await.unwrap();
+        assert_eq!(sink.pending_count().await.unwrap(), 2);
+
+        let now = Utc::now();
+        let due = sink.due_jobs(now, 10).await.unwrap();
+        assert_eq!(due.iter().map(|j| (j.id, j.subscriber.as_str(), j.attempts)).collect::<Vec<_>>(), [(first, "fleet", 0), (second, "crm", 0)]);
+        assert_eq!(sink.due_jobs(now, 1).await.unwrap().len(), 1);
+
+        // Retry later: not due until its next attempt
+        sink.reschedule(first, 1, now + chrono::Duration::seconds(30), "HTTP 503").await.unwrap();
+        assert_eq!(sink.due_jobs(now, 10).await.unwrap().iter().map(|j| j.id).collect::<Vec<_>>(), [second]);
+        let due = sink.due_jobs(now + chrono::Duration::seconds(31), 10).await.unwrap();
+        assert_eq!((due[1].id, due[1].attempts), (first, 1));
+
+        sink.mark_delivered(second).await.unwrap();
+        sink.dead_letter(first, 5, "HTTP 503").await.unwrap();
+        assert_eq!(sink.pending_count().await.unwrap(), 0);
+
+        let (id, subscriber, attempts, last_error): (i64, String, i32, String) = sqlx::query_as("SELECT id, subscriber, attempts, last_error FROM webhook_dead_letter")
+            .fetch_one(&sink.pool).await.unwrap();
+        assert_eq!((id, subscriber.as_str(), attempts, last_error.as_str()), (first, "fleet", 5, "HTTP 503"));
+    }
+
+    #[tokio::test]
+    async fn test_device_sessions() {
+        let sink = sink().await;
+        let connected_at = Utc::now();
+        let id = sink.open_session("356307042441013", "10.0.0.7:40312", connected_at).await.unwrap().unwrap();
+        sink.close_session(id, &SessionSummary {
+            disconnected_at: connected_at + chrono::Duration::seconds(90),
+            reason: "timeout".to_string(),
+            packets: 3,
+            records: 12,
+            bytes_received: 1200,
+            bytes_sent: 13,
+            duration_ms: 90_000,
+        }).await.unwrap();
+
+        let row = sqlx::query("SELECT * FROM device_sessions WHERE id = ?").bind(id).fetch_one(&sink.pool).await.unwrap();
+        assert_eq!(row.get::<String, _>("imei"), "356307042441013");
+        assert_eq!(row.get::<String, _>("peer_addr"), "10.0.0.7:40312");
+        assert_eq!(row.get::<DateTime<Utc>, _>("connected_at"), connected_at);
+        assert_eq!(row.get::<String, _>("disconnect_reason"), "timeout");
+        assert_eq!((row.get::<i64, _>("packets"), row.get::<i64, _>("records")), (3, 12));
+        assert_eq!((row.get::<i64, _>("bytes_received"), row.get::<i64, _>("bytes_sent")), (1200, 13));
+        assert_eq!(row.get::<i64, _>("duration_ms"), 90_000);
+    }
+
+    #[tokio::test]
+    async fn test_device_registry() {
+        let sink = sink().await;
+        assert_eq!(sink.device_status("356307042441013").await.unwrap(), None);
+        assert!(sink.enroll("356307042441013", DeviceStatus::Quarantine).await.unwrap());
+        // Already known: the operator's status is kept
+        assert!(!sink.enroll("356307042441013", DeviceStatus::Active).await.unwrap());
+        assert_eq!(sink.device_status("356307042441013").await.unwrap(), Some(DeviceStatus::Quarantine));
+    }
+}
@@ -0,0 +1,103 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicI64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use crate::config::get_settings;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerState {
+    Starting,
+    Listening,
+    /// Shutdown requested: no new connections, current ones finishing.
+    Draining,
+    Stopped,
+}
+
+impl ListenerState {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => ListenerState::Listening,
+            2 => ListenerState::Draining,
+            3 => ListenerState::Stopped,
+            _ => ListenerState::Starting,
+        }
+    }
+}
+
+/// Process-wide runtime state shown by `/health` and `/readyz`.
+pub struct ServerStatus {
+    listener: AtomicU8,
+    active_connections: AtomicUsize,
+    /// Unix millis of the last packet from any device, 0 = none yet.
+    last_packet_ms: AtomicI64,
+    /// IMEIs that have a `device_last_seen_timestamp_seconds` series.
+    tracked_devices: OnceLock<Mutex<HashSet<String>>>,
+}
+
+pub static SERVER: ServerStatus = ServerStatus {
+    listener: AtomicU8::new(0),
+    active_connections: AtomicUsize::new(0),
+    last_packet_ms: AtomicI64::new(0),
+    tracked_devices: OnceLock::new(),
+};
+
+impl ServerStatus {
+    pub fn set_listener(&self, state: ListenerState) {
+        self.listener.store(state as u8, Ordering::Relaxed);
+    }
+
+    pub fn listener(&self) -> ListenerState {
+        ListenerState::from_u8(self.listener.load(Ordering::Relaxed))
+    }
+
+    pub fn connection_opened(&self) {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        metrics::gauge!("tcp_connections_active").increment(1.0);
+    }
+
+    pub fn connection_closed(&self, opened_at: Instant) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+        metrics::gauge!("tcp_connections_active").decrement(1.0);
+        metrics::histogram!("connection_duration_seconds").record(opened_at.elapsed().as_secs_f64());
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn packet_received(&self, at: DateTime<Utc>) {
+        self.last_packet_ms.fetch_max(at.timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub fn last_packet_at(&self) -> Option<DateTime<Utc>> {
+        match self.last_packet_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Utc.timestamp_millis_opt(ms).single(),
+        }
+    }
+
+    /// Per-IMEI last-seen gauge, when `metrics.device_last_seen` is on. Only the
+    /// first `metrics.max_devices` IMEIs get a series, so cardinality stays bounded.
+    pub fn device_seen(&self, imei: &str, at: DateTime<Utc>) {
+        let settings = get_settings();
+        if !settings.metrics.device_last_seen || imei.is_empty() {
+            return;
+        }
+
+        let mut tracked = self.tracked_devices.get_or_init(Default::default).lock().unwrap();
+        if !tracked.contains(imei) {
+            if tracked.len() >= settings.metrics.max_devices {
+                metrics::counter!("device_last_seen_overflow_total").increment(1);
+                return;
+            }
+            tracked.insert(imei.to_string());
+        }
+        drop(tracked);
+
+        metrics::gauge!("device_last_seen_timestamp_seconds", "imei" => imei.to_string()).set(at.timestamp() as f64);
+    }
+}
@@ -0,0 +1,177 @@
+use arc_swap::ArcSwap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::config::{get_settings, TlsSettings};
+use crate::notifications::{NotificationService, Severity};
+
+/// How often the certificate files are checked for rotation.
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("cannot read {0}: {1}")]
+    Io(String, #[source] std::io::Error),
+    #[error("no certificate in {0}")]
+    NoCertificate(String),
+    #[error("no private key in {0}")]
+    NoPrivateKey(String),
+    #[error("invalid certificate or key: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+fn read_pem(path: &str) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| TlsError::Io(path.to_string(), e))
+}
+
+/// Build the rustls config from `server.tls.cert_path` / `key_path`.
+pub fn load_server_config(settings: &TlsSettings) -> Result<Arc<ServerConfig>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut read_pem(&settings.cert_path)?)
+        .collect::<Result<Vec<CertificateDer<'static>>, _>>()
+        .map_err(|e| TlsError::Io(settings.cert_path.clone(), e))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate(settings.cert_path.clone()));
+    }
+
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut read_pem(&settings.key_path)?)
+        .map_err(|e| TlsError::Io(settings.key_path.clone(), e))?
+        .ok_or_else(|| TlsError::NoPrivateKey(settings.key_path.clone()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// Paths and modification times the current config was loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fingerprint {
+    cert_path: String,
+    key_path: String,
+    modified: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl Fingerprint {
+    fn of(settings: &TlsSettings) -> Self {
+        let modified = |path: &str| Path::new(path).metadata().and_then(|m| m.modified()).ok();
+        Fingerprint {
+            cert_path: settings.cert_path.clone(),
+            key_path: settings.key_path.clone(),
+            modified: (modified(&settings.cert_path), modified(&settings.key_path)),
+        }
+    }
+}
+
+/// Certificate served by the TLS listener. New connections pick up a rotated
+/// certificate; established sessions keep theirs.
+pub struct TlsCertificates {
+    config: ArcSwap<ServerConfig>,
+    loaded: Mutex<Fingerprint>,
+}
+
+impl TlsCertificates {
+    pub fn load(settings: &TlsSettings) -> Result<Arc<Self>, TlsError> {
+        let fingerprint = Fingerprint::of(settings);
+        let config = load_server_config(settings)?;
+        Ok(Arc::new(TlsCertificates { config: ArcSwap::new(config), loaded: Mutex::new(fingerprint) }))
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.config.load_full())
+    }
+
+    /// Reload when the paths (config reload) or the files changed. A broken
+    /// new certificate is reported and the previous one stays in use.
+    fn reload_if_changed(&self, settings: &TlsSettings) {
+        let fingerprint = Fingerprint::of(settings);
+        if *self.loaded.lock().unwrap() == fingerprint {
+            return;
+        }
+        match load_server_config(settings) {
+            Ok(config) => {
+                self.config.store(config);
+                info!("Reloaded TLS certificate from {}", settings.cert_path);
+                metrics::counter!("tls_certificate_reloads_total", "result" => "ok").increment(1);
+            }
+            Err(e) => {
+                error!("Keeping the current TLS certificate, reload failed: {}", e);
+                metrics::counter!("tls_certificate_reloads_total", "result" => "error").increment(1);
+                NotificationService::notify(Severity::Warning, "⚠️ TLS certificate reload failed", &e.to_string());
+            }
+        }
+        // Not retried until the files change again
+        *self.loaded.lock().unwrap() = fingerprint;
+    }
+
+    pub fn spawn_watcher(self: &Arc<Self>, shutdown: CancellationToken) {
+        let certificates = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ROTATION_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => certificates.reload_if_changed(&get_settings().server.tls),
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Self-signed certificate for `localhost`, written to a temp dir.
+    pub fn self_signed(dir: &Path) -> TlsSettings {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::create_dir_all(dir).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+        TlsSettings {
+            enabled: true,
+            port: 6443,
+            cert_path: cert_path.to_string_lossy().into_owned(),
+            key_path: key_path.to_string_lossy().into_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_certificate_rotation() {
+        let dir = std::env::temp_dir().join(format!("nc-teltonika-tls-{}", std::process::id()));
+        let settings = self_signed(&dir);
+        let certificates = TlsCertificates::load(&settings).unwrap();
+        let first = certificates.config.load_full();
+
+        // Unchanged files: nothing reloaded
+        certificates.reload_if_changed(&settings);
+        assert!(Arc::ptr_eq(&first, &certificates.config.load_full()));
+
+        // Broken key: the old certificate stays (mtime reset, a rewrite can land in the same tick)
+        std::fs::write(&settings.key_path, "garbage").unwrap();
+        certificates.loaded.lock().unwrap().modified = (None, None);
+        certificates.reload_if_changed(&settings);
+        assert!(Arc::ptr_eq(&first, &certificates.config.load_full()));
+
+        // Renewed certificate in new files
+        let renewed = self_signed(&dir.join("renewed"));
+        certificates.reload_if_changed(&renewed);
+        assert!(!Arc::ptr_eq(&first, &certificates.config.load_full()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
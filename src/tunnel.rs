@@ -0,0 +1,453 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use ssh2::{CheckResult, KnownHostFileKind, Session};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::config::SshSettings;
+use crate::notifications::{NotificationService, Severity};
+use crate::utils::backoff_delay;
+
+const DEFAULT_SSH_PORT: u16 = 22;
+const DEFAULT_REMOTE_PORT: u16 = 5432;
+/// The forward always targets the Postgres port on the SSH host itself.
+const REMOTE_HOST: &str = "127.0.0.1";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const KEEPALIVE_SECS: u32 = 15;
+const BACKOFF_BASE_MS: u64 = 1000;
+const BACKOFF_MAX_MS: u64 = 60_000;
+/// Sleep of the forwarding loop when no byte moved, doubled on every idle
+/// round up to the max (see `SshTunnel` for the latency this adds).
+const IDLE_SLEEP_MIN: Duration = Duration::from_millis(2);
+const IDLE_SLEEP_MAX: Duration = Duration::from_millis(50);
+const BUFFER_SIZE: usize = 16 * 1024;
+/// Channel opens failing in a row before the session is considered dead.
+/// A half-open TCP peer may take minutes to fail the keepalive.
+const MAX_CHANNEL_FAILURES: u32 = 3;
+
+#[derive(Debug, Error)]
+pub enum TunnelError {
+    #[error("ssh: {0}")]
+    Ssh(#[from] ssh2::Error),
+    #[error("io: {0}")]
+    Io(#[from] io::Error),
+    #[error("host {0} is not in the known_hosts file")]
+    UnknownHost(String),
+    #[error("host key of {0} does not match the known_hosts file")]
+    HostKeyMismatch(String),
+    #[error("authentication as '{0}' failed")]
+    Auth(String),
+    #[error("tunnel not up after {0:?}: {1}")]
+    NotUp(Duration, String),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelState {
+    Connecting,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelStatus {
+    pub state: TunnelState,
+    pub since: DateTime<Utc>,
+    /// Sessions re-established after a drop.
+    pub reconnects: u64,
+    /// Why the tunnel is not up; cleared once it is.
+    pub last_error: Option<String>,
+    /// Up at least once, so the next `Up` is a reconnect.
+    #[serde(skip)]
+    was_up: bool,
+}
+
+impl TunnelStatus {
+    fn new() -> Self {
+        TunnelStatus { state: TunnelState::Connecting, since: Utc::now(), reconnects: 0, last_error: None, was_up: false }
+    }
+}
+
+/// Everything the tunnel thread needs, resolved from `[ssh]` and `[database]`.
+#[derive(Debug, Clone)]
+struct TunnelConfig {
+    user: String,
+    host: String,
+    port: u16,
+    key_path: Option<String>,
+    key_passphrase: Option<String>,
+    known_hosts: std::path::PathBuf,
+    local_port: u16,
+    remote_port: u16,
+}
+
+/// In-process SSH port forward: 127.0.0.1:`database.port` -> Postgres on the SSH host.
+///
+/// A dedicated thread owns the libssh2 session and moves bytes between local
+/// sockets and SSH channels. The server key must be in `known_hosts`, the forward
+/// is probed before the tunnel reports `up`, and a dropped session is
+/// re-established with exponential backoff while the local port stays bound.
+///
+/// The thread polls instead of blocking on the sockets: after an idle period it
+/// sleeps up to `IDLE_SLEEP_MAX` between rounds, so the first database round-trip
+/// after a pause can take up to 50 ms longer. Busy traffic resets the sleep to
+/// `IDLE_SLEEP_MIN`, and an idle tunnel costs about 20 wakeups/s.
+pub struct SshTunnel {
+    status: watch::Receiver<TunnelStatus>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SshTunnel {
+    /// Bind the local port and start connecting in the background (see `wait_up`).
+    pub fn start(ssh: &SshSettings, local_port: u16) -> Result<Self, TunnelError> {
+        let config = TunnelConfig {
+            user: ssh.user.clone().unwrap_or_default(),
+            host: ssh.host.clone().unwrap_or_default(),
+            port: ssh.port.unwrap_or(DEFAULT_SSH_PORT),
+            key_path: ssh.key_path.clone().filter(|k| !k.is_empty()),
+            key_passphrase: ssh.key_passphrase.clone().filter(|p| !p.is_empty()),
+            known_hosts: ssh.known_hosts_file(),
+            local_port,
+            remote_port: ssh.tunnel_port.unwrap_or(DEFAULT_REMOTE_PORT),
+        };
+        info!("Starting SSH tunnel to {}@{}:{} (127.0.0.1:{} -> {}:{})...",
+            config.user, config.host, config.port, local_port, REMOTE_HOST, config.remote_port);
+
+        let listener = TcpListener::bind(("127.0.0.1", local_port))?;
+        listener.set_nonblocking(true)?;
+
+        let (tx, rx) = watch::channel(TunnelStatus::new());
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name("ssh-tunnel".into())
+            .spawn(move || run(config, listener, tx, thread_stop))?;
+
+        tokio::spawn(alert_on_transitions(rx.clone()));
+
+        Ok(SshTunnel { status: rx, stop, thread: Some(thread) })
+    }
+
+    /// Wait until the forward has been verified once.
+    pub async fn wait_up(&self, timeout: Duration) -> Result<(), TunnelError> {
+        let mut status = self.status.clone();
+        let up = tokio::time::timeout(timeout, async {
+            status.wait_for(|s| s.state == TunnelState::Up).await.is_ok()
+        }).await;
+        match up {
+            Ok(true) => Ok(()),
+            _ => {
+                let last_error = self.status.borrow().last_error.clone().unwrap_or_else(|| "still connecting".into());
+                Err(TunnelError::NotUp(timeout, last_error))
+            }
+        }
+    }
+
+    /// Live status, e.g. for `/health`.
+    pub fn status(&self) -> watch::Receiver<TunnelStatus> {
+        self.status.clone()
+    }
+
+    /// Stop the tunnel thread and wait for it off the runtime threads: it may be
+    /// inside a blocking handshake or channel open for up to `CONNECT_TIMEOUT`.
+    pub async fn shutdown(mut self) {
+        info!("Stopping SSH tunnel...");
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+        }
+    }
+}
+
+impl Drop for SshTunnel {
+    // Without `shutdown`, the thread is told to stop and left to exit on its own
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Alert when an established tunnel drops and when it comes back.
+async fn alert_on_transitions(mut status: watch::Receiver<TunnelStatus>) {
+    let mut was_up = false;
+    while status.changed().await.is_ok() {
+        let current = status.borrow_and_update().clone();
+        match current.state {
+            TunnelState::Up if was_up => {}
+            TunnelState::Up => {
+                if current.reconnects > 0 {
+                    NotificationService::notify(Severity::Info, "✅ SSH tunnel restored", &format!("Reconnects: {}", current.reconnects));
+                }
+                was_up = true;
+            }
+            // A quick Down -> Connecting may be seen as Connecting only
+            TunnelState::Down | TunnelState::Connecting if was_up => {
+                NotificationService::notify(Severity::Error, "❌ SSH tunnel down", current.last_error.as_deref().unwrap_or(""));
+                was_up = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn set_state(tx: &watch::Sender<TunnelStatus>, state: TunnelState, error: Option<String>) {
+    tx.send_modify(|s| {
+        if s.state != state {
+            s.since = Utc::now();
+        }
+        if state == TunnelState::Up {
+            if s.was_up && s.state != TunnelState::Up {
+                s.reconnects += 1;
+            }
+            s.was_up = true;
+            s.last_error = None;
+        } else if error.is_some() {
+            s.last_error = error;
+        }
+        s.state = state;
+    });
+}
+
+/// Tunnel thread: keep a session up until `stop` is set.
+fn run(config: TunnelConfig, listener: TcpListener, tx: watch::Sender<TunnelStatus>, stop: Arc<AtomicBool>) {
+    let mut failures = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        set_state(&tx, TunnelState::Connecting, None);
+        let started = Instant::now();
+
+        match run_session(&config, &listener, &tx, &stop) {
+            Ok(()) => break,
+            Err(e) => {
+                // A session that stayed up a while starts the backoff over
+                failures = if started.elapsed() > Duration::from_millis(BACKOFF_MAX_MS) { 1 } else { failures + 1 };
+                let delay = backoff_delay(failures, BACKOFF_BASE_MS, BACKOFF_MAX_MS);
+                warn!("SSH tunnel down: {}, reconnecting in {:?}", e, delay);
+                set_state(&tx, TunnelState::Down, Some(e.to_string()));
+
+                let until = Instant::now() + delay;
+                while Instant::now() < until && !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            }
+        }
+    }
+}
+
+fn connect(config: &TunnelConfig) -> Result<Session, TunnelError> {
+    let addr = (config.host.as_str(), config.port).to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("cannot resolve {}", config.host)))?;
+    let tcp = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+
+    let mut session = Session::new()?;
+    session.set_tcp_stream(tcp);
+    session.set_timeout(CONNECT_TIMEOUT.as_millis() as u32);
+    session.handshake()?;
+
+    verify_host_key(&session, config)?;
+
+    match &config.key_path {
+        Some(key) => session.userauth_pubkey_file(&config.user, None, Path::new(key), config.key_passphrase.as_deref())?,
+        None => session.userauth_agent(&config.user)?,
+    }
+    if !session.authenticated() {
+        return Err(TunnelError::Auth(config.user.clone()));
+    }
+
+    Ok(session)
+}
+
+fn verify_host_key(session: &Session, config: &TunnelConfig) -> Result<(), TunnelError> {
+    let (key, _) = session.host_key()
+        .ok_or_else(|| TunnelError::UnknownHost(config.host.clone()))?;
+    let mut known_hosts = session.known_hosts()?;
+    known_hosts.read_file(&config.known_hosts, KnownHostFileKind::OpenSSH)?;
+
+    match known_hosts.check_port(&config.host, config.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(TunnelError::HostKeyMismatch(config.host.clone())),
+        CheckResult::NotFound | CheckResult::Failure => Err(TunnelError::UnknownHost(config.host.clone())),
+    }
+}
+
+/// One SSH session: returns `Ok` when stopped, `Err` when the session is lost.
+fn run_session(config: &TunnelConfig, listener: &TcpListener, tx: &watch::Sender<TunnelStatus>, stop: &AtomicBool) -> Result<(), TunnelError> {
+    let session = connect(config)?;
+
+    // Check the far end actually accepts connections before reporting `up`
+    drop(session.channel_direct_tcpip(REMOTE_HOST, config.remote_port, None)?);
+    info!("SSH tunnel up: 127.0.0.1:{} -> {}@{} -> {}:{}", config.local_port, config.user, config.host, REMOTE_HOST, config.remote_port);
+    set_state(tx, TunnelState::Up, None);
+
+    session.set_keepalive(true, KEEPALIVE_SECS);
+    session.set_blocking(false);
+
+    let mut forwards: Vec<Forward> = Vec::new();
+    let mut next_keepalive = Instant::now();
+    let mut idle_sleep = IDLE_SLEEP_MIN;
+    let mut channel_failures = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut progress = false;
+
+        match listener.accept() {
+            Ok((tcp, peer)) => {
+                tcp.set_nonblocking(true)?;
+                // Opening a channel is a round-trip; do it in blocking mode
+                session.set_blocking(true);
+                let channel = session.channel_direct_tcpip(REMOTE_HOST, config.remote_port, None);
+                session.set_blocking(false);
+                match channel {
+                    Ok(channel) => {
+                        debug!("SSH tunnel: forwarding {}", peer);
+                        forwards.push(Forward::new(tcp, channel));
+                        channel_failures = 0;
+                    }
+                    Err(e) => {
+                        channel_failures += 1;
+                        warn!("SSH tunnel: cannot open a channel for {} ({} in a row): {}", peer, channel_failures, e);
+                        // Only this connection is dropped, unless the session itself looks dead
+                        if channel_failures >= MAX_CHANNEL_FAILURES {
+                            return Err(e.into());
+                        }
+                    }
+                }
+                progress = true;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => warn!("SSH tunnel: accept failed: {}", e),
+        }
+
+        forwards.retain_mut(|forward| match forward.pump() {
+            Ok((moved, done)) => {
+                progress |= moved;
+                !done
+            }
+            Err(e) => {
+                debug!("SSH tunnel: closing forward: {}", e);
+                false
+            }
+        });
+
+        if Instant::now() >= next_keepalive {
+            match session.keepalive_send() {
+                Ok(secs) => next_keepalive = Instant::now() + Duration::from_secs(secs.max(1) as u64),
+                Err(e) if e.code() == ssh2::ErrorCode::Session(libssh2_sys::LIBSSH2_ERROR_EAGAIN) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if progress {
+            idle_sleep = IDLE_SLEEP_MIN;
+        } else {
+            std::thread::sleep(idle_sleep);
+            idle_sleep = (idle_sleep * 2).min(IDLE_SLEEP_MAX);
+        }
+    }
+
+    Ok(())
+}
+
+/// One local connection and its SSH channel, both non-blocking.
+struct Forward {
+    tcp: TcpStream,
+    channel: ssh2::Channel,
+    to_remote: Vec<u8>,
+    to_local: Vec<u8>,
+    local_eof: bool,
+    remote_eof: bool,
+}
+
+fn would_block(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock
+}
+
+impl Forward {
+    fn new(tcp: TcpStream, channel: ssh2::Channel) -> Self {
+        Forward { tcp, channel, to_remote: Vec::new(), to_local: Vec::new(), local_eof: false, remote_eof: false }
+    }
+
+    /// Move what can be moved without blocking. Returns (bytes moved, finished).
+    fn pump(&mut self) -> io::Result<(bool, bool)> {
+        let mut moved = false;
+        let mut buf = [0u8; BUFFER_SIZE];
+
+        if self.to_remote.is_empty() && !self.local_eof {
+            match self.tcp.read(&mut buf) {
+                Ok(0) => {
+                    self.local_eof = true;
+                    let _ = self.channel.send_eof();
+                }
+                Ok(n) => self.to_remote.extend_from_slice(&buf[..n]),
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !self.to_remote.is_empty() {
+            match self.channel.write(&self.to_remote) {
+                Ok(n) => {
+                    self.to_remote.drain(..n);
+                    moved = true;
+                }
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        if self.to_local.is_empty() && !self.remote_eof {
+            match self.channel.read(&mut buf) {
+                Ok(0) => self.remote_eof = self.channel.eof(),
+                Ok(n) => self.to_local.extend_from_slice(&buf[..n]),
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !self.to_local.is_empty() {
+            match self.tcp.write(&self.to_local) {
+                Ok(n) => {
+                    self.to_local.drain(..n);
+                    moved = true;
+                }
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok((moved, self.remote_eof && self.to_local.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_transitions() {
+        let (tx, rx) = watch::channel(TunnelStatus::new());
+
+        // A failed first connect is not a reconnect
+        set_state(&tx, TunnelState::Down, Some("connection refused".into()));
+        set_state(&tx, TunnelState::Connecting, None);
+        set_state(&tx, TunnelState::Up, None);
+        assert_eq!(rx.borrow().state, TunnelState::Up);
+        assert_eq!(rx.borrow().reconnects, 0);
+        assert_eq!(rx.borrow().last_error, None);
+
+        set_state(&tx, TunnelState::Down, Some("connection reset".into()));
+        set_state(&tx, TunnelState::Connecting, None);
+        assert_eq!(rx.borrow().last_error.as_deref(), Some("connection reset"));
+
+        set_state(&tx, TunnelState::Up, None);
+        assert_eq!(rx.borrow().reconnects, 1);
+        assert_eq!(rx.borrow().last_error, None);
+    }
+}
@@ -1,5 +1,14 @@
+use std::time::Duration;
+
 use super::parser::models::{AvlRecord, IoElement};
 
+/// Exponential backoff: `base * 2^(attempts - 1)`, capped at `max`.
+pub fn backoff_delay(attempts: i32, base_ms: u64, max_ms: u64) -> Duration {
+    let exp = attempts.saturating_sub(1).clamp(0, 32) as u32;
+    let delay = base_ms.saturating_mul(2u64.saturating_pow(exp));
+    Duration::from_millis(delay.min(max_ms))
+}
+
 fn format_io_value(io: &IoElement) -> String {
     match &io.dimension {
         Some(dim) => format!("{} {}", io.value, dim),
@@ -50,3 +59,17 @@ pub fn format_record(record: &AvlRecord) -> String {
     log += "  ---\n";
     log
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(1, 1000, 60_000), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(2, 1000, 60_000), Duration::from_millis(2000));
+        assert_eq!(backoff_delay(4, 1000, 60_000), Duration::from_millis(8000));
+        assert_eq!(backoff_delay(10, 1000, 60_000), Duration::from_millis(60_000));
+        assert_eq!(backoff_delay(1000, 1000, 60_000), Duration::from_millis(60_000));
+    }
+}
@@ -0,0 +1,209 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+use crate::parser::models::AvlRecord;
+
+pub mod queue;
+pub mod signature;
+pub mod subscribers;
+
+pub use queue::WebhookQueue;
+pub use subscribers::{jobs_for_batch, jobs_for_geofence, jobs_for_presence};
+
+/// Bump whenever a field is renamed or removed from `WebhookPayload`.
+pub const WEBHOOK_SCHEMA_VERSION: u32 = 1;
+
+static HTTP_CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// Shared client so connections to the API are pooled instead of rebuilt per packet.
+pub fn http_client() -> &'static Client {
+    HTTP_CLIENT.get_or_init(Client::new)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecordSummary {
+    pub timestamp: DateTime<Utc>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub speed: i16,
+    pub event_id: u16,
+}
+
+impl From<&AvlRecord> for RecordSummary {
+    fn from(record: &AvlRecord) -> Self {
+        RecordSummary {
+            timestamp: record.timestamp,
+            latitude: record.gps.latitude,
+            longitude: record.gps.longitude,
+            speed: record.gps.speed,
+            event_id: record.event_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookPayload {
+    pub schema_version: u32,
+    pub imei: String,
+    pub received_at: DateTime<Utc>,
+    /// Id of the `teltonika_data` row, when the storage backend returned one.
+    pub row_id: Option<i64>,
+    pub record_count: usize,
+    /// Full decoded records (`webhook.include_records = true`)...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub records: Option<Vec<AvlRecord>>,
+    /// ...or one compact line per record otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Vec<RecordSummary>>,
+}
+
+impl WebhookPayload {
+    pub fn new(imei: &str, received_at: DateTime<Utc>, row_id: Option<i64>, records: &[AvlRecord], include_records: bool) -> Self {
+        let (records_field, summary) = if include_records {
+            (Some(records.to_vec()), None)
+        } else {
+            (None, Some(records.iter().map(RecordSummary::from).collect()))
+        };
+
+        WebhookPayload {
+            schema_version: WEBHOOK_SCHEMA_VERSION,
+            imei: imei.to_string(),
+            received_at,
+            row_id,
+            record_count: records.len(),
+            records: records_field,
+            summary,
+        }
+    }
+}
+
+/// Body sent to `events = "presence"` subscribers when a device goes offline or comes back.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PresencePayload {
+    pub schema_version: u32,
+    /// "device_offline" or "device_online".
+    pub event: String,
+    pub imei: String,
+    /// Last packet from the device.
+    pub last_seen: DateTime<Utc>,
+    pub expected_interval_secs: u64,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Body sent to `events = "geofence_events"` subscribers when a device enters or leaves a zone.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeofencePayload {
+    pub schema_version: u32,
+    /// "geofence_enter" or "geofence_exit".
+    pub event: String,
+    pub imei: String,
+    /// `name` of the zone in `[[geofences]]`.
+    pub geofence: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Time of the GPS fix that crossed the boundary.
+    pub timestamp: DateTime<Utc>,
+    pub received_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct PostError {
+    /// HTTP status when the endpoint answered.
+    pub status: Option<u16>,
+    pub timeout: bool,
+    pub message: String,
+}
+
+impl PostError {
+    /// `status` label of `webhook_deliveries_total`.
+    pub fn status_label(&self) -> String {
+        match self.status {
+            Some(code) => code.to_string(),
+            None if self.timeout => "timeout".to_string(),
+            None => "error".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for PostError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// POST a JSON body and return the HTTP status. Any non-2xx answer is reported
+/// as an error so the queue retries it.
+pub async fn post_json(url: &str, body: &str, headers: &HashMap<String, String>, timeout: Duration) -> Result<u16, PostError> {
+    let mut req = http_client().post(url)
+        .header("Content-Type", "application/json");
+    for (name, value) in headers {
+        req = req.header(name.as_str(), value.as_str());
+    }
+
+    let res = req
+        .body(body.to_string())
+        .timeout(timeout)
+        .send()
+        .await
+        // reqwest puts the URL in its errors; they end up in alerts and `last_error`
+        .map_err(|e| PostError { status: None, timeout: e.is_timeout(), message: e.without_url().to_string() })?;
+
+    let status = res.status();
+    if status.is_success() {
+        Ok(status.as_u16())
+    } else {
+        Err(PostError { status: Some(status.as_u16()), timeout: false, message: format!("HTTP {}", status) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::models::{IoGroup, TeltonikaGps};
+
+    fn record() -> AvlRecord {
+        AvlRecord {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            priority: 1,
+            gps: TeltonikaGps { longitude: 5.374, latitude: 43.2951, altitude: 12, angle: 90, satellites: 9, speed: 4 },
+            event_id: 239,
+            io_groups: IoGroup { n1: vec![], n2: vec![], n4: vec![], n8: vec![], nx: vec![] },
+            io_elements: vec![],
+            properties_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_payload_shape() {
+        let received_at = DateTime::from_timestamp(1_700_000_005, 0).unwrap();
+
+        let full = serde_json::to_value(WebhookPayload::new("356307042441013", received_at, None, &[record()], true)).unwrap();
+        assert_eq!(full["schema_version"], WEBHOOK_SCHEMA_VERSION);
+        assert_eq!(full["imei"], "356307042441013");
+        assert_eq!(full["received_at"], "2023-11-14T22:13:25Z");
+        // Present as null, not omitted: consumers rely on the key
+        assert!(full["row_id"].is_null() && full.as_object().unwrap().contains_key("row_id"));
+        assert_eq!(full["record_count"], 1);
+        assert_eq!(full["records"].as_array().unwrap().len(), 1);
+        assert!(full.get("summary").is_none());
+
+        let compact = serde_json::to_value(WebhookPayload::new("356307042441013", received_at, Some(42), &[record()], false)).unwrap();
+        assert_eq!(compact["row_id"], 42);
+        assert!(compact.get("records").is_none());
+        assert_eq!(compact["summary"], serde_json::json!([{
+            "timestamp": "2023-11-14T22:13:20Z",
+            "latitude": 43.2951,
+            "longitude": 5.374,
+            "speed": 4,
+            "event_id": 239,
+        }]));
+
+        // Round trip
+        let parsed: WebhookPayload = serde_json::from_value(compact).unwrap();
+        assert_eq!((parsed.row_id, parsed.record_count), (Some(42), 1));
+        assert!(parsed.records.is_none());
+    }
+}
@@ -0,0 +1,332 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use super::post_json;
+use crate::utils::backoff_delay;
+use super::signature::add_signature_headers;
+use super::subscribers::find_subscriber;
+use crate::config::{get_settings, Settings};
+use crate::notifications::{NotificationService, Severity};
+use crate::sink::{NewWebhookJob, SinkError, WebhookJob, WebhookStore};
+
+/// Background delivery of webhooks.
+///
+/// `handle_client` only enqueues (one insert) and ACKs the device right away;
+/// a single worker task drains the store, retrying failures with exponential
+/// backoff and dead-lettering jobs after `webhook.max_attempts`.
+pub struct WebhookQueue {
+    store: Arc<dyn WebhookStore>,
+    wake: Notify,
+    /// Fixed settings instead of the live config (tests).
+    pinned: Option<Arc<Settings>>,
+}
+
+impl WebhookQueue {
+    pub fn new(store: Arc<dyn WebhookStore>) -> Arc<Self> {
+        Arc::new(WebhookQueue { store, wake: Notify::new(), pinned: None })
+    }
+
+    #[cfg(test)]
+    pub fn with_settings(store: Arc<dyn WebhookStore>, settings: Settings) -> Arc<Self> {
+        Arc::new(WebhookQueue { store, wake: Notify::new(), pinned: Some(Arc::new(settings)) })
+    }
+
+    fn settings(&self) -> Arc<Settings> {
+        self.pinned.clone().unwrap_or_else(get_settings)
+    }
+
+    pub async fn enqueue(&self, job: NewWebhookJob) {
+        match self.store.enqueue(&job).await {
+            Ok(id) => {
+                debug!("Queued webhook #{} for {}", id, job.subscriber);
+                metrics::gauge!("webhook_queue_depth").increment(1.0);
+                self.wake.notify_one();
+            }
+            Err(e) => {
+                metrics::counter!("webhook_enqueue_failures_total").increment(1);
+                error!("Failed to queue webhook for {}: {}", job.subscriber, e);
+                NotificationService::sql_error(e.sql(), &format!("{:?}", e));
+            }
+        }
+    }
+
+    /// Run the worker until `shutdown` is cancelled; it then delivers whatever is
+    /// due and exits. Jobs not delivered by then stay queued for the next start.
+    pub fn spawn_worker(self: &Arc<Self>, shutdown: CancellationToken) -> JoinHandle<()> {
+        let queue = self.clone();
+        tokio::spawn(async move { queue.run(shutdown).await })
+    }
+
+    async fn run(&self, shutdown: CancellationToken) {
+        info!("Webhook delivery worker started");
+        self.refresh_depth().await;
+
+        loop {
+            let processed = self.process_due().await;
+            if processed == 0 {
+                if shutdown.is_cancelled() {
+                    break;
+                }
+                let poll = Duration::from_millis(self.settings().webhook.poll_interval_ms);
+                tokio::select! {
+                    _ = self.wake.notified() => {}
+                    _ = tokio::time::sleep(poll) => {}
+                    _ = shutdown.cancelled() => {}
+                }
+            }
+        }
+
+        match self.store.pending_count().await {
+            Ok(0) | Err(_) => info!("Webhook delivery worker stopped"),
+            Ok(n) => info!("Webhook delivery worker stopped, {} job(s) left for retry after restart", n),
+        }
+    }
+
+    /// Deliver every job that is due. Returns how many were attempted.
+    pub async fn process_due(&self) -> usize {
+        let settings = self.settings();
+        let jobs = match self.store.due_jobs(chrono::Utc::now(), settings.webhook.batch_size).await {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                error!("Failed to read webhook queue: {}", e);
+                return 0;
+            }
+        };
+
+        let count = jobs.len();
+        let mut deliveries = JoinSet::new();
+        for job in jobs {
+            let (store, settings) = (self.store.clone(), settings.clone());
+            let span = info_span!("webhook.deliver", job_id = job.id, subscriber = %job.subscriber, attempt = job.attempts + 1);
+            deliveries.spawn(async move { deliver(store.as_ref(), &settings, job).await }.instrument(span));
+        }
+        while deliveries.join_next().await.is_some() {}
+
+        if count > 0 {
+            self.refresh_depth().await;
+        }
+        count
+    }
+
+    /// Jobs waiting for delivery or retry.
+    pub async fn depth(&self) -> Result<i64, SinkError> {
+        self.store.pending_count().await
+    }
+
+    async fn refresh_depth(&self) {
+        if let Ok(depth) = self.store.pending_count().await {
+            metrics::gauge!("webhook_queue_depth").set(depth as f64);
+        }
+    }
+}
+
+async fn deliver(store: &dyn WebhookStore, settings: &Settings, job: WebhookJob) {
+    let timeout = Duration::from_millis(settings.webhook.timeout_ms);
+
+    // URL, headers and secret all come from the current config, so an edit applies
+    // to jobs already queued as a whole (`job.url` only records the original target)
+    let Some(subscriber) = find_subscriber(settings, &job.subscriber) else {
+        warn!("Webhook #{}: subscriber '{}' is no longer configured, moving to dead-letter", job.id, job.subscriber);
+        metrics::counter!("webhook_deliveries_total", "result" => "dead_letter", "status" => "unknown_subscriber").increment(1);
+        if let Err(e) = store.dead_letter(job.id, job.attempts, "subscriber no longer configured").await {
+            error!("Failed to update webhook #{}: {}", job.id, e);
+        }
+        return;
+    };
+    let mut headers = subscriber.headers.clone();
+    if let Some(secret) = subscriber.secret.as_deref() {
+        // Signed per attempt: retries get a fresh timestamp
+        add_signature_headers(&mut headers, secret, chrono::Utc::now().timestamp(), &job.body);
+    }
+
+    let start = Instant::now();
+    let res = post_json(&subscriber.url, &job.body, &headers, timeout).await;
+    metrics::histogram!("webhook_delivery_duration_seconds").record(start.elapsed().as_secs_f64());
+    let status = match &res {
+        Ok(code) => code.to_string(),
+        Err(e) => e.status_label(),
+    };
+    let res = res.map_err(|e| e.message);
+
+    let attempts = job.attempts + 1;
+    let outcome = match res {
+        Ok(_) => {
+            info!("✅ Sent webhook #{} to {}", job.id, job.subscriber);
+            metrics::counter!("webhook_deliveries_total", "result" => "delivered", "status" => status).increment(1);
+            store.mark_delivered(job.id).await
+        }
+        Err(err) if attempts >= settings.webhook.max_attempts => {
+            warn!("Webhook #{} to {} failed {} times, moving to dead-letter: {}", job.id, job.subscriber, attempts, err);
+            metrics::counter!("webhook_deliveries_total", "result" => "dead_letter", "status" => status).increment(1);
+            NotificationService::notify(
+                Severity::Error,
+                "❌ Webhook moved to dead-letter",
+                // No URL: subscriber URLs often embed tokens (see config::is_secret_key)
+                &format!("Subscriber: {}<br/>Queued at: {}<br/>Attempts: {}<br/>Error: {}", job.subscriber, job.created_at, attempts, err),
+            );
+            store.dead_letter(job.id, attempts, &err).await
+        }
+        Err(err) => {
+            let delay = backoff_delay(attempts, settings.webhook.backoff_base_ms, settings.webhook.backoff_max_ms);
+            debug!("Webhook #{} to {} failed (attempt {}), retrying in {:?}: {}", job.id, job.subscriber, attempts, delay, err);
+            metrics::counter!("webhook_deliveries_total", "result" => "retry", "status" => status).increment(1);
+            let next = chrono::Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+            store.reschedule(job.id, attempts, next, &err).await
+        }
+    };
+
+    if let Err(e) = outcome {
+        error!("Failed to update webhook #{}: {}", job.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::MemorySink;
+    use crate::webhook::subscribers::{EventFilter, SubscriberSettings};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Minimal HTTP endpoint answering every request with `status`; returns its URL
+    /// and the head (request line and headers, lowercased) of every request received.
+    async fn endpoint(status: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                received.lock().unwrap().push(String::from_utf8_lossy(&buf[..n]).to_lowercase());
+                let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, requests)
+    }
+
+    fn subscriber(url: &str, api_key: &str) -> SubscriberSettings {
+        SubscriberSettings {
+            name: "test".to_string(),
+            url: url.to_string(),
+            imeis: vec![],
+            group: None,
+            events: EventFilter::All,
+            headers: HashMap::from([("X-Api-Key".to_string(), api_key.to_string())]),
+            secret: None,
+        }
+    }
+
+    /// Queue delivering to a single `test` subscriber at `url`.
+    fn queue(sink: &Arc<MemorySink>, url: &str) -> Arc<WebhookQueue> {
+        let mut settings = (*get_settings()).clone();
+        settings.webhook.subscribers = vec![subscriber(url, "k1")];
+        WebhookQueue::with_settings(sink.clone(), settings)
+    }
+
+    fn job(url: &str) -> NewWebhookJob {
+        NewWebhookJob { subscriber: "test".to_string(), url: url.to_string(), body: "{}".to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_retry_backoff_and_dead_letter() {
+        let settings = get_settings().webhook.clone();
+        let (url, requests) = endpoint("503 Service Unavailable").await;
+        let sink = Arc::new(MemorySink::new());
+        let queue = queue(&sink, &url);
+        queue.enqueue(job(&url)).await;
+
+        for attempt in 1..settings.max_attempts {
+            let before = chrono::Utc::now();
+            assert_eq!(queue.process_due().await, 1);
+            let (queued, next_attempt_at) = sink.queued_jobs().pop().unwrap();
+            assert_eq!(queued.attempts, attempt);
+            let delay = chrono::Duration::from_std(backoff_delay(attempt, settings.backoff_base_ms, settings.backoff_max_ms)).unwrap();
+            assert!(next_attempt_at >= before + delay && next_attempt_at <= chrono::Utc::now() + delay);
+            // Not due again before the backoff is over
+            assert_eq!(queue.process_due().await, 0);
+            sink.expire_backoffs();
+        }
+
+        // Last attempt: out of the queue and into the dead-letter table
+        assert_eq!(queue.process_due().await, 1);
+        assert!(sink.queued_jobs().is_empty());
+        let dead = sink.dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].0.attempts, settings.max_attempts);
+        assert!(dead[0].1.contains("503"));
+        assert_eq!(requests.lock().unwrap().len(), settings.max_attempts as usize);
+    }
+
+    #[tokio::test]
+    async fn test_errors_leave_out_the_url() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook?token=s3cr3t", listener.local_addr().unwrap());
+        drop(listener);
+        let sink = Arc::new(MemorySink::new());
+        let queue = queue(&sink, &url);
+        queue.enqueue(job(&url)).await;
+
+        for _ in 0..get_settings().webhook.max_attempts {
+            assert_eq!(queue.process_due().await, 1);
+            sink.expire_backoffs();
+        }
+        let dead = sink.dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert!(!dead[0].1.contains("s3cr3t"), "{}", dead[0].1);
+    }
+
+    #[tokio::test]
+    async fn test_queued_jobs_follow_the_subscriber_config() {
+        let (old_url, old_requests) = endpoint("200 OK").await;
+        let (new_url, new_requests) = endpoint("200 OK").await;
+        let sink = Arc::new(MemorySink::new());
+        queue(&sink, &old_url).enqueue(job(&old_url)).await;
+
+        // Reloaded with a new URL and API key: both apply to the job already queued
+        let mut settings = (*get_settings()).clone();
+        settings.webhook.subscribers = vec![subscriber(&new_url, "k2")];
+        assert_eq!(WebhookQueue::with_settings(sink.clone(), settings.clone()).process_due().await, 1);
+        assert!(old_requests.lock().unwrap().is_empty());
+        let requests = new_requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("x-api-key: k2"), "{}", requests[0]);
+
+        // Subscriber removed: dead-lettered without sending it unauthenticated anywhere
+        sink.enqueue(&job(&new_url)).await.unwrap();
+        settings.webhook.subscribers.clear();
+        assert_eq!(WebhookQueue::with_settings(sink.clone(), settings).process_due().await, 1);
+        assert_eq!(new_requests.lock().unwrap().len(), 1);
+        assert!(sink.queued_jobs().is_empty());
+        let dead = sink.dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert_eq!((dead[0].0.attempts, dead[0].1.as_str()), (0, "subscriber no longer configured"));
+    }
+
+    #[tokio::test]
+    async fn test_worker_flushes_on_shutdown() {
+        let (url, requests) = endpoint("200 OK").await;
+        let sink = Arc::new(MemorySink::new());
+        let queue = queue(&sink, &url);
+        let shutdown = CancellationToken::new();
+        let worker = queue.spawn_worker(shutdown.clone());
+
+        // Queued by the last packets, right before the worker is told to stop
+        queue.enqueue(job(&url)).await;
+        queue.enqueue(job(&url)).await;
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), worker).await.unwrap().unwrap();
+
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(sink.queued_jobs().is_empty());
+        assert!(sink.dead_letters().is_empty());
+    }
+}
@@ -0,0 +1,48 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+
+/// HMAC-SHA256 over `"{timestamp}.{body}"`, hex encoded.
+///
+/// The receiver recomputes it with the shared secret, compares in constant
+/// time and rejects timestamps too far from its own clock (replay protection).
+/// See the README "Webhook Signatures" section.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Add the timestamp and signature headers for one delivery attempt.
+pub fn add_signature_headers(headers: &mut HashMap<String, String>, secret: &str, timestamp: i64, body: &str) {
+    headers.insert(TIMESTAMP_HEADER.to_string(), timestamp.to_string());
+    headers.insert(SIGNATURE_HEADER.to_string(), format!("sha256={}", sign(secret, timestamp, body)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_known_vector() {
+        // python3 -c "import hmac,hashlib; print(hmac.new(b'topsecret', b'1700000000.{\"imei\":\"356307042441013\"}', hashlib.sha256).hexdigest())"
+        let body = r#"{"imei":"356307042441013"}"#;
+        assert_eq!(sign("topsecret", 1700000000, body), "f7fbe8dc46a58566d6409e1aafb6445d62200b0bd6749a23e1804d8f61f88662");
+    }
+
+    #[test]
+    fn test_signature_headers() {
+        let mut headers = HashMap::new();
+        add_signature_headers(&mut headers, "topsecret", 1700000000, "{}");
+        assert_eq!(headers[TIMESTAMP_HEADER], "1700000000");
+        assert!(headers[SIGNATURE_HEADER].starts_with("sha256="));
+        assert_ne!(sign("topsecret", 1700000001, "{}"), sign("topsecret", 1700000000, "{}"));
+    }
+}
@@ -0,0 +1,218 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{GeofencePayload, PresencePayload, WebhookPayload};
+use crate::config::Settings;
+use crate::parser::models::AvlRecord;
+use crate::sink::NewWebhookJob;
+
+/// Subscriber name used for the built-in Nauticoncept endpoint.
+pub const NAUTICONCEPT_SUBSCRIBER: &str = "nauticoncept";
+
+// Teltonika event IO IDs used by the filters below.
+const IGNITION_IO: u16 = 239;
+const AUTO_GEOFENCE_IO: u16 = 175;
+const GEOFENCE_ZONE_IOS: std::ops::RangeInclusive<u16> = 155..=159;
+const ALARM_IOS: [u16; 4] = [246, 247, 249, 252]; // towing, crash, jamming, unplug
+const PRIORITY_PANIC: u8 = 2;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EventFilter {
+    /// Every record.
+    #[default]
+    All,
+    /// Panic-priority records and towing/crash/jamming/unplug events.
+    Alarms,
+    /// Records generated by an ignition change.
+    Ignition,
+    /// Records generated by a geofence zone event.
+    Geofence,
+    /// No records: only device offline/online transitions (see `presence`).
+    Presence,
+    /// No records: only enter/exit events of the server-side `[[geofences]]`.
+    GeofenceEvents,
+}
+
+impl EventFilter {
+    pub fn matches(&self, record: &AvlRecord) -> bool {
+        match self {
+            EventFilter::All => true,
+            EventFilter::Alarms => record.priority == PRIORITY_PANIC || ALARM_IOS.contains(&record.event_id),
+            EventFilter::Ignition => record.event_id == IGNITION_IO,
+            EventFilter::Geofence => record.event_id == AUTO_GEOFENCE_IO || GEOFENCE_ZONE_IOS.contains(&record.event_id),
+            EventFilter::Presence | EventFilter::GeofenceEvents => false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SubscriberSettings {
+    pub name: String,
+    pub url: String,
+    /// Only these IMEIs (empty = no IMEI restriction).
+    #[serde(default)]
+    pub imeis: Vec<String>,
+    /// Only IMEIs of this group from `[groups]`.
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub events: EventFilter,
+    /// Extra headers sent with every request (e.g. an API key).
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Shared secret; when set, requests carry `X-Timestamp` and `X-Signature`.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+impl SubscriberSettings {
+    pub fn accepts_imei(&self, imei: &str, groups: &HashMap<String, Vec<String>>) -> bool {
+        if !self.imeis.is_empty() && !self.imeis.iter().any(|i| i == imei) {
+            return false;
+        }
+        match &self.group {
+            Some(group) => groups.get(group).is_some_and(|members| members.iter().any(|i| i == imei)),
+            None => true,
+        }
+    }
+}
+
+/// The legacy Nauticoncept endpoint, kept as an implicit subscriber when `nauticoncept_url` is set.
+fn nauticoncept_subscriber(settings: &Settings) -> Option<SubscriberSettings> {
+    let base_url = &settings.webhook.nauticoncept_url;
+    if base_url.is_empty() {
+        return None;
+    }
+    Some(SubscriberSettings {
+        name: NAUTICONCEPT_SUBSCRIBER.to_string(),
+        url: format!("{}/modmessage-ttk/message-webhook", base_url),
+        imeis: vec![],
+        group: None,
+        events: EventFilter::All,
+        headers: HashMap::new(),
+        secret: settings.webhook.nauticoncept_secret.clone().filter(|s| !s.is_empty()),
+    })
+}
+
+/// Every configured subscriber, Nauticoncept first.
+pub fn all_subscribers(settings: &Settings) -> Vec<SubscriberSettings> {
+    nauticoncept_subscriber(settings).into_iter()
+        .chain(settings.webhook.subscribers.iter().cloned())
+        .collect()
+}
+
+/// Look up a subscriber by name (URL, headers and secret are resolved at delivery time).
+pub fn find_subscriber(settings: &Settings, name: &str) -> Option<SubscriberSettings> {
+    all_subscribers(settings).into_iter().find(|s| s.name == name)
+}
+
+/// Fan one AVL packet out to every matching subscriber.
+///
+/// Each subscriber gets its own job containing only the records that pass its
+/// event filter; subscribers with no matching record are skipped.
+pub fn jobs_for_batch(settings: &Settings, imei: &str, received_at: DateTime<Utc>, row_id: Option<i64>, records: &[AvlRecord]) -> Vec<NewWebhookJob> {
+    let mut jobs = Vec::new();
+
+    for subscriber in all_subscribers(settings) {
+        if !subscriber.accepts_imei(imei, &settings.groups) {
+            continue;
+        }
+
+        let matching: Vec<AvlRecord> = records.iter().filter(|r| subscriber.events.matches(r)).cloned().collect();
+        if matching.is_empty() {
+            continue;
+        }
+
+        // Legacy consumers only expect a ping and re-read the DB themselves
+        let body = if subscriber.name == NAUTICONCEPT_SUBSCRIBER && settings.webhook.legacy_empty_body {
+            serde_json::json!({})
+        } else {
+            let payload = WebhookPayload::new(imei, received_at, row_id, &matching, settings.webhook.include_records);
+            serde_json::json!(payload)
+        };
+
+        jobs.push(NewWebhookJob {
+            subscriber: subscriber.name.clone(),
+            url: subscriber.url.clone(),
+            body: body.to_string(),
+        });
+    }
+
+    jobs
+}
+
+/// One job per subscriber with `events = "presence"` that accepts the IMEI.
+pub fn jobs_for_presence(settings: &Settings, payload: &PresencePayload) -> Vec<NewWebhookJob> {
+    let body = serde_json::json!(payload).to_string();
+    all_subscribers(settings).into_iter()
+        .filter(|s| s.events == EventFilter::Presence && s.accepts_imei(&payload.imei, &settings.groups))
+        .map(|s| NewWebhookJob { subscriber: s.name, url: s.url, body: body.clone() })
+        .collect()
+}
+
+/// One job per subscriber with `events = "geofence_events"` that accepts the IMEI.
+pub fn jobs_for_geofence(settings: &Settings, payload: &GeofencePayload) -> Vec<NewWebhookJob> {
+    let body = serde_json::json!(payload).to_string();
+    all_subscribers(settings).into_iter()
+        .filter(|s| s.events == EventFilter::GeofenceEvents && s.accepts_imei(&payload.imei, &settings.groups))
+        .map(|s| NewWebhookJob { subscriber: s.name, url: s.url, body: body.clone() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::models::{IoGroup, TeltonikaGps};
+
+    fn record(priority: u8, event_id: u16) -> AvlRecord {
+        AvlRecord {
+            timestamp: Utc::now(),
+            priority,
+            gps: TeltonikaGps { longitude: 0.0, latitude: 0.0, altitude: 0, angle: 0, satellites: 0, speed: 0 },
+            event_id,
+            io_groups: IoGroup { n1: vec![], n2: vec![], n4: vec![], n8: vec![], nx: vec![] },
+            io_elements: vec![],
+            properties_count: 0,
+        }
+    }
+
+    fn subscriber(imeis: Vec<&str>, group: Option<&str>) -> SubscriberSettings {
+        SubscriberSettings {
+            name: "test".into(),
+            url: "http://localhost".into(),
+            imeis: imeis.into_iter().map(String::from).collect(),
+            group: group.map(String::from),
+            events: EventFilter::All,
+            headers: HashMap::new(),
+            secret: None,
+        }
+    }
+
+    #[test]
+    fn test_event_filters() {
+        assert!(EventFilter::All.matches(&record(0, 0)));
+        assert!(EventFilter::Alarms.matches(&record(2, 0)));
+        assert!(EventFilter::Alarms.matches(&record(0, 247)));
+        assert!(!EventFilter::Alarms.matches(&record(1, 239)));
+        assert!(EventFilter::Ignition.matches(&record(0, 239)));
+        assert!(!EventFilter::Ignition.matches(&record(0, 240)));
+        assert!(EventFilter::Geofence.matches(&record(0, 155)));
+        assert!(EventFilter::Geofence.matches(&record(0, 175)));
+        assert!(!EventFilter::Geofence.matches(&record(0, 0)));
+        assert!(!EventFilter::Presence.matches(&record(2, 247)));
+    }
+
+    #[test]
+    fn test_subscriber_imei_filters() {
+        let groups = HashMap::from([("marina".to_string(), vec!["111".to_string()])]);
+
+        assert!(subscriber(vec![], None).accepts_imei("999", &groups));
+        assert!(subscriber(vec!["999"], None).accepts_imei("999", &groups));
+        assert!(!subscriber(vec!["111"], None).accepts_imei("999", &groups));
+        assert!(subscriber(vec![], Some("marina")).accepts_imei("111", &groups));
+        assert!(!subscriber(vec![], Some("marina")).accepts_imei("999", &groups));
+        assert!(!subscriber(vec![], Some("unknown")).accepts_imei("111", &groups));
+    }
+}